@@ -0,0 +1,73 @@
+//! 合法手の列挙をテキスト形式の盤面（総当たり）とビットボードで比較する。
+//! `cargo bench --bench move_generation`で実行する。
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::Instant;
+use tableturfbattle::{catalog::parse_card_catalog, Card, CardId, Environment, Field, State};
+
+fn measure(name: &str, iterations: usize, mut f: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<40} {:>10.1?}/iter ({} iterations)",
+        name,
+        elapsed / iterations as u32,
+        iterations
+    );
+}
+
+fn main() {
+    let catalog =
+        parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+    let cards = catalog
+        .iter()
+        .map(|card| (card.id, card))
+        .collect::<HashMap<CardId, &Card>>();
+    let env = Environment::new(2, 15, 4, 12, true);
+
+    // ランダムに進めたゲームの各ターンの盤面を集める
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut states = vec![];
+    for _ in 0..10 {
+        let decks = (0..env.player_size)
+            .map(|_| {
+                (0..env.deck_size)
+                    .map(|_| catalog.choose(&mut rng).unwrap().id)
+                    .collect::<Vec<CardId>>()
+            })
+            .collect::<Vec<_>>();
+        let mut state = State::new(&env, &cards, &Field::default(), &decks).unwrap();
+        while !state.is_done(&env) {
+            let actions = (0..env.player_size)
+                .map(|player_id| {
+                    *state
+                        .generate_valid_actions(&cards, player_id)
+                        .choose(&mut rng)
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            states.push(state.clone());
+            state.apply(&env, &cards, &actions).unwrap();
+        }
+    }
+
+    let iterations = 20;
+    measure("generate_valid_actions_naive", iterations, || {
+        for state in states.iter() {
+            black_box(state.generate_valid_actions_naive(&cards, 0));
+        }
+    });
+    measure(
+        "generate_valid_actions (anchors+bitboard)",
+        iterations,
+        || {
+            for state in states.iter_mut() {
+                black_box(state.generate_valid_actions(&cards, 0));
+            }
+        },
+    );
+}
@@ -0,0 +1,38 @@
+//! `Match::run`でランダムなbot同士を自己対戦させ、1局あたりの時間を測る。
+//! `cargo bench --bench self_play`で実行する。
+use std::time::Instant;
+use tableturfbattle::{
+    agent::{Agent, RandomAgent},
+    catalog::parse_card_catalog,
+    match_runner::Match,
+    seed::{derive_seed, game_rng},
+    Environment, Field,
+};
+
+fn main() {
+    let catalog =
+        parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+    let env = Environment::new(2, 20, 4, 17, true);
+    let field = Field::default();
+
+    let n_games = 1000;
+    let start = Instant::now();
+    for seed in 0..n_games {
+        let mut agents: Vec<Box<dyn Agent>> = (0..env.player_size)
+            .map(|player_id| -> Box<dyn Agent> {
+                Box::new(RandomAgent::new(
+                    "random",
+                    game_rng(derive_seed(seed, player_id as u64)),
+                ))
+            })
+            .collect();
+        Match::run(&env, &field, &catalog, &mut agents, seed).unwrap();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<40} {:>10.1?}/game ({:.0} games/min)",
+        "Match::run (random vs random)",
+        elapsed / n_games as u32,
+        n_games as f64 * 60.0 / elapsed.as_secs_f64()
+    );
+}
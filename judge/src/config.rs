@@ -0,0 +1,442 @@
+//! judgeの設定。TOMLかJSONの設定ファイルを読み、コマンドライン引数で上書きする。
+use serde::Deserialize;
+use std::{collections::BTreeSet, fmt::Display, path::Path, str::FromStr, time::Duration};
+use tableturfbattle::{
+    agent::TimeLimits, deck::DeckConstraints, match_runner::FailurePolicy, rules::Rules,
+    tournament::Format, CardId, Environment, MAX_PLAYER_SIZE,
+};
+
+pub const USAGE: &str = "\
+usage: judge [tournament] [options]
+
+  --config <path>            TOMLかJSONの設定ファイル。以下のオプションは設定ファイルより優先する
+  --bot <command [args...]>  botのコマンドと引数を空白区切りで。プレイヤーの人数分（大会では2つ以上）並べる。
+                             builtin:randomはjudgeの中で動くランダムなbot
+  --card-catalog <path>      カードカタログ（既定: resources/card_catalog_sample.json）
+  --field <id|name>          ステージ（既定: まっすぐストリート）
+  --field-catalog <path>     フィールドカタログ（既定: resources/field_catalog.json）
+  --player-size <n>          プレイヤーの人数（既定: 2）
+  --deck-size <n>            デッキの枚数（既定: 20）
+  --hand-size <n>            手札の枚数（既定: 4）
+  --max-turn <n>             ターン数（既定: 17）
+  --duplicated-pick <bool>   同じカードをデッキに複数入れられるか（既定: true）
+  --max-copies <n>           同じカードを入れられる最大の枚数
+  --banned-card <id>         デッキに入れられないカード。繰り返し指定できる
+  --rules <name>             official、protected_special_attack、retried_activation のどれか
+  --seed <u64>               最初の対局の種。省略するとランダム
+  --games <n>                対局数。大会では組み合わせごとの対局数（既定: 1、大会では10）
+  --record <path>            棋譜の出力先。省略すると標準出力。複数の対局では拡張子の前に番号を付ける。
+                             拡張子が.jsonならJSONの棋譜、それ以外はテキストの棋譜を書く
+  --format <name>            大会の形式 round_robin または gauntlet
+  --threads <n>              大会で並列に進める対局数
+  --deck-time-limit <ms>     botの起動からデッキを選ぶまでの持ち時間（既定: 10000）
+  --mulligan-time-limit <ms> マリガンの持ち時間（既定: 10000）
+  --turn-time-limit <ms>     1ターンの持ち時間（既定: 10000）
+  --clock <ms>               全ターンの持ち時間の合計。省略すると制限しない
+  --on-failure <policy>      持ち時間切れやbotの終了を forfeit（反則負け）か pass（自動でパス）にする（既定: forfeit）
+  --stderr-limit <bytes>     1回の応答で記録するbotの標準エラー出力の上限（既定: 65536）
+";
+
+/// 起動するbot。`command`が`builtin:random`ならプロセス内のbotを使う。
+#[derive(Deserialize, Eq, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BotConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// 棋譜に記録するbotのバージョン
+    #[serde(default)]
+    pub version: Option<String>,
+}
+impl BotConfig {
+    /// `command arg1 arg2`の形の文字列から作る
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut words = s.split_whitespace().map(|word| word.to_string());
+        Ok(Self {
+            command: words.next().ok_or("--bot needs a command")?,
+            args: words.collect(),
+            version: None,
+        })
+    }
+}
+impl Display for BotConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.command)?;
+        for arg in self.args.iter() {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+/// 設定ファイルの中身。書かなかった項目は既定値を使う。
+#[derive(Deserialize, Eq, PartialEq, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchConfig {
+    pub bots: Vec<BotConfig>,
+    pub card_catalog: Option<String>,
+    pub field: Option<String>,
+    pub field_catalog: Option<String>,
+    pub player_size: Option<usize>,
+    pub deck_size: Option<usize>,
+    pub hand_size: Option<usize>,
+    pub max_turn: Option<usize>,
+    pub duplicated_pick: Option<bool>,
+    pub max_copies: Option<usize>,
+    pub banned_cards: Vec<CardId>,
+    pub rules: Option<String>,
+    pub seed: Option<u64>,
+    pub games: Option<usize>,
+    pub record: Option<String>,
+    pub format: Option<String>,
+    pub threads: Option<usize>,
+    /// 持ち時間はミリ秒で指定する
+    pub deck_time_limit: Option<u64>,
+    pub mulligan_time_limit: Option<u64>,
+    pub turn_time_limit: Option<u64>,
+    pub clock: Option<u64>,
+    pub on_failure: Option<String>,
+    pub stderr_limit: Option<usize>,
+}
+impl MatchConfig {
+    /// 拡張子が`.json`ならJSON、それ以外はTOMLとして読む
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|why| format!("couldn't read {}: {}", path, why))?;
+        if Path::new(path).extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&text).map_err(|why| format!("{}: {}", path, why))
+        } else {
+            toml::from_str(&text).map_err(|why| format!("{}: {}", path, why))
+        }
+    }
+    /// `--config`があればその設定ファイルを読み、残りのオプションで上書きする。
+    /// 知らないオプションや値の無いオプションはエラーにする。
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let args = parse_args(args)?;
+        let args = args.as_slice();
+        let mut config = match arg_value(args, "--config") {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+        let bots = arg_values(args, "--bot");
+        if !bots.is_empty() {
+            config.bots = bots
+                .into_iter()
+                .map(BotConfig::parse)
+                .collect::<Result<_, _>>()?;
+        }
+        let banned_cards = arg_values(args, "--banned-card");
+        if !banned_cards.is_empty() {
+            config.banned_cards = banned_cards
+                .into_iter()
+                .map(|card_id| parse("--banned-card", card_id))
+                .collect::<Result<_, _>>()?;
+        }
+        let string = |name| arg_value(args, name).map(str::to_string);
+        override_with(&mut config.card_catalog, string("--card-catalog"));
+        override_with(&mut config.field, string("--field"));
+        override_with(&mut config.field_catalog, string("--field-catalog"));
+        override_with(&mut config.rules, string("--rules"));
+        override_with(&mut config.record, string("--record"));
+        override_with(&mut config.format, string("--format"));
+        override_with(&mut config.player_size, parsed(args, "--player-size")?);
+        override_with(&mut config.deck_size, parsed(args, "--deck-size")?);
+        override_with(&mut config.hand_size, parsed(args, "--hand-size")?);
+        override_with(&mut config.max_turn, parsed(args, "--max-turn")?);
+        override_with(
+            &mut config.duplicated_pick,
+            parsed(args, "--duplicated-pick")?,
+        );
+        override_with(&mut config.max_copies, parsed(args, "--max-copies")?);
+        override_with(&mut config.seed, parsed(args, "--seed")?);
+        override_with(&mut config.games, parsed(args, "--games")?);
+        override_with(&mut config.threads, parsed(args, "--threads")?);
+        override_with(
+            &mut config.deck_time_limit,
+            parsed(args, "--deck-time-limit")?,
+        );
+        override_with(
+            &mut config.mulligan_time_limit,
+            parsed(args, "--mulligan-time-limit")?,
+        );
+        override_with(
+            &mut config.turn_time_limit,
+            parsed(args, "--turn-time-limit")?,
+        );
+        override_with(&mut config.clock, parsed(args, "--clock")?);
+        override_with(&mut config.on_failure, string("--on-failure"));
+        override_with(&mut config.stderr_limit, parsed(args, "--stderr-limit")?);
+        Ok(config)
+    }
+    pub fn environment(&self) -> Result<Environment, String> {
+        let rules = match &self.rules {
+            Some(rules) => rules.parse::<Rules>()?,
+            None => Rules::default(),
+        };
+        let player_size = self.player_size.unwrap_or(2);
+        let deck_size = self.deck_size.unwrap_or(20);
+        let hand_size = self.hand_size.unwrap_or(4);
+        let max_turn = self.max_turn.unwrap_or(17);
+        // `Environment::new`のassertより先に分かりやすいエラーにする
+        if !(1..=MAX_PLAYER_SIZE).contains(&player_size) {
+            return Err(format!(
+                "player size must be in 1..={} but was {}",
+                MAX_PLAYER_SIZE, player_size
+            ));
+        }
+        if deck_size + 1 < max_turn + hand_size {
+            return Err(format!(
+                "deck size {} is too small for {} turns with {} cards in hand",
+                deck_size, max_turn, hand_size
+            ));
+        }
+        Ok(Environment::new(
+            player_size,
+            deck_size,
+            hand_size,
+            max_turn,
+            self.duplicated_pick.unwrap_or(true),
+        )
+        .with_rules(rules)
+        .with_deck_constraints(DeckConstraints {
+            max_copies: self.max_copies,
+            banned_cards: self.banned_cards.iter().copied().collect::<BTreeSet<_>>(),
+        }))
+    }
+    pub fn card_catalog(&self) -> &str {
+        self.card_catalog
+            .as_deref()
+            .unwrap_or("resources/card_catalog_sample.json")
+    }
+    pub fn field_catalog(&self) -> &str {
+        self.field_catalog
+            .as_deref()
+            .unwrap_or("resources/field_catalog.json")
+    }
+    pub fn format(&self) -> Result<Format, String> {
+        self.format
+            .as_ref()
+            .map_or(Ok(Format::default()), |format| {
+                format
+                    .parse()
+                    .map_err(|_| format!("unknown tournament format: {}", format))
+            })
+    }
+    pub fn time_limits(&self) -> TimeLimits {
+        let default = TimeLimits::default();
+        let limit = |ms: Option<u64>, default| ms.map_or(default, Duration::from_millis);
+        TimeLimits {
+            deck: limit(self.deck_time_limit, default.deck),
+            mulligan: limit(self.mulligan_time_limit, default.mulligan),
+            turn: limit(self.turn_time_limit, default.turn),
+            clock: self.clock.map(Duration::from_millis),
+        }
+    }
+    pub fn stderr_limit(&self) -> usize {
+        self.stderr_limit.unwrap_or(1 << 16)
+    }
+    pub fn failure_policy(&self) -> Result<FailurePolicy, String> {
+        self.on_failure
+            .as_ref()
+            .map_or(Ok(FailurePolicy::default()), |policy| {
+                policy
+                    .parse()
+                    .map_err(|_| format!("unknown failure policy: {}", policy))
+            })
+    }
+    /// `game`局目の棋譜の出力先。複数の対局では`kifu.txt`を`kifu_0001.txt`のように番号付きにする。
+    pub fn record_path(&self, game: usize, n_games: usize) -> Option<String> {
+        let path = self.record.as_ref()?;
+        if n_games <= 1 {
+            return Some(path.clone());
+        }
+        let path = Path::new(path);
+        let stem = path
+            .file_stem()
+            .map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+        let file_name = match path.extension() {
+            Some(ext) => format!("{}_{:04}.{}", stem, game, ext.to_string_lossy()),
+            None => format!("{}_{:04}", stem, game),
+        };
+        Some(path.with_file_name(file_name).display().to_string())
+    }
+}
+
+fn override_with<T>(value: &mut Option<T>, arg: Option<T>) {
+    if arg.is_some() {
+        *value = arg;
+    }
+}
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", name, value))
+}
+fn parsed<T: FromStr>(args: &[(&str, &str)], name: &str) -> Result<Option<T>, String> {
+    arg_value(args, name)
+        .map(|value| parse(name, value))
+        .transpose()
+}
+// 値を取るオプション。`USAGE`に並べたものと同じ
+const OPTIONS: [&str; 24] = [
+    "--config",
+    "--bot",
+    "--card-catalog",
+    "--field",
+    "--field-catalog",
+    "--player-size",
+    "--deck-size",
+    "--hand-size",
+    "--max-turn",
+    "--duplicated-pick",
+    "--max-copies",
+    "--banned-card",
+    "--rules",
+    "--seed",
+    "--games",
+    "--record",
+    "--format",
+    "--threads",
+    "--deck-time-limit",
+    "--mulligan-time-limit",
+    "--turn-time-limit",
+    "--clock",
+    "--on-failure",
+    "--stderr-limit",
+];
+// コマンドライン引数を左から読み、`(オプション, 値)`の組に分ける。
+// サブコマンドの`tournament`は最初の引数にだけ書ける
+fn parse_args(args: &[String]) -> Result<Vec<(&str, &str)>, String> {
+    let mut rest = args.iter().skip(1).map(String::as_str).peekable();
+    rest.next_if_eq(&"tournament");
+    let mut options = vec![];
+    while let Some(name) = rest.next() {
+        if !OPTIONS.contains(&name) {
+            return Err(format!("unknown option: {}", name));
+        }
+        let value = rest
+            .next()
+            .ok_or_else(|| format!("{} needs a value", name))?;
+        options.push((name, value));
+    }
+    Ok(options)
+}
+// `name value`の形で指定されたオプションの値
+fn arg_value<'a>(args: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    arg_values(args, name).pop()
+}
+// `name value`の形で繰り返し指定されたオプションの値
+fn arg_values<'a>(args: &[(&str, &'a str)], name: &str) -> Vec<&'a str> {
+    args.iter()
+        .filter(|(option, _)| *option == name)
+        .map(|(_, value)| *value)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn args(s: &str) -> Vec<String> {
+        s.split(' ').map(|s| s.to_string()).collect()
+    }
+    #[test]
+    fn test_config_file() {
+        let toml = toml::from_str::<MatchConfig>(
+            r#"
+            deck_size = 15
+            max_turn = 12
+            duplicated_pick = false
+            rules = "protected_special_attack"
+            banned_cards = [1, 2]
+
+            [[bots]]
+            command = "python3"
+            args = ["bot.py", "--depth", "3"]
+
+            [[bots]]
+            command = "builtin:random"
+            "#,
+        )
+        .unwrap();
+        let json = serde_json::from_str::<MatchConfig>(
+            r#"{
+                "deck_size": 15,
+                "max_turn": 12,
+                "duplicated_pick": false,
+                "rules": "protected_special_attack",
+                "banned_cards": [1, 2],
+                "bots": [
+                    {"command": "python3", "args": ["bot.py", "--depth", "3"]},
+                    {"command": "builtin:random"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(toml, json);
+        assert_eq!(toml.bots[0].to_string(), "python3 bot.py --depth 3");
+        let env = toml.environment().unwrap();
+        assert_eq!(env.deck_size, 15);
+        assert!(!env.is_deplicated_pick_enabled);
+        assert_eq!(env.rules, Rules::ProtectedSpecialAttack);
+        assert_eq!(env.deck_constraints.banned_cards, BTreeSet::from([1, 2]));
+        assert!(toml::from_str::<MatchConfig>("deck = 15").is_err());
+        assert!(toml::from_str::<MatchConfig>("deck_size = 15")
+            .unwrap()
+            .environment()
+            .is_err());
+    }
+    #[test]
+    fn test_from_args() {
+        let config = MatchConfig::from_args(&args(
+            "judge --bot builtin:random --bot target/release/bot --max-turn 12 --games 3 --record tmp/kifu.txt",
+        ))
+        .unwrap();
+        assert_eq!(
+            config.bots[1],
+            BotConfig::parse("target/release/bot").unwrap()
+        );
+        assert_eq!(config.environment().unwrap().max_turn, 12);
+        assert_eq!(
+            config.record_path(2, 3),
+            Some("tmp/kifu_0002.txt".to_string())
+        );
+        assert_eq!(config.record_path(0, 1), Some("tmp/kifu.txt".to_string()));
+        assert_eq!(config.card_catalog(), "resources/card_catalog_sample.json");
+        assert_eq!(config.time_limits(), TimeLimits::default());
+        assert_eq!(config.failure_policy(), Ok(FailurePolicy::Forfeit));
+        let config = MatchConfig::from_args(&args(
+            "judge --turn-time-limit 500 --clock 3000 --on-failure pass",
+        ))
+        .unwrap();
+        assert_eq!(config.time_limits().turn, Duration::from_millis(500));
+        assert_eq!(config.time_limits().clock, Some(Duration::from_secs(3)));
+        assert_eq!(config.failure_policy(), Ok(FailurePolicy::Pass));
+        assert!(MatchConfig::from_args(&args("judge --on-failure hoge"))
+            .unwrap()
+            .failure_policy()
+            .is_err());
+        assert!(MatchConfig::from_args(&args("judge --seed")).is_err());
+        assert!(MatchConfig::from_args(&args("judge --seed -1")).is_err());
+        assert!(MatchConfig::from_args(&args("judge --rules hoge"))
+            .unwrap()
+            .environment()
+            .is_err());
+
+        // 知らないオプションや、サブコマンドの位置が違う引数は受け付けない
+        assert_eq!(
+            MatchConfig::from_args(&args("judge --deck-szie 3")),
+            Err("unknown option: --deck-szie".to_string())
+        );
+        assert!(MatchConfig::from_args(&args("judge --games 2 tournament")).is_err());
+        assert!(MatchConfig::from_args(&args("judge tournament --games 2")).is_ok());
+        assert!(MatchConfig::from_args(&args("judge --bot")).is_err());
+        // オプションの値はオプション名と同じ文字列でも値として読む
+        let config = MatchConfig::from_args(&args("judge --record --seed --games 3")).unwrap();
+        assert_eq!(config.record.as_deref(), Some("--seed"));
+        assert_eq!(config.seed, None);
+        assert_eq!(config.games, Some(3));
+        for option in OPTIONS {
+            assert!(USAGE.contains(&format!("  {} <", option)), "{}", option);
+        }
+    }
+}
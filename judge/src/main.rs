@@ -1,344 +1,293 @@
-use log::{debug, info};
-use rand::{rngs::ThreadRng, seq::SliceRandom};
-use serde::{Deserialize, Serialize};
-use std::io::{BufRead, BufReader};
-use std::process::{Command, Stdio};
-use std::{collections::HashMap, process::Child};
-use std::{io::prelude::*, path::Path};
+mod config;
+
+use config::{BotConfig, MatchConfig, USAGE};
+use log::info;
+use rand::Rng;
+use std::{fmt::Display, path::Path, process::Command, time::SystemTime};
 use tableturfbattle::{
-    Action, Card, CardId, CardShape, Environment, Field, MulliganAction, PlayerId, State,
+    agent::{Agent, ProcessAgent, RandomAgent},
+    catalog::{find_field, load_card_catalog, load_field_catalog},
+    check_field,
+    kifu::{format_date, Kifu},
+    match_runner::{FailurePolicy, Match},
+    record::GameRecord,
+    seed::{derive_seed, game_rng, SEED_ENV_VAR},
+    text_protocol::format_record,
+    tournament::{run_games, schedule, Crosstable, Format},
+    Card, Environment, Field, GameError,
 };
 
-#[derive(Serialize, Deserialize)]
-struct CardJson {
-    id: usize,
-    name: String,
-    cost: usize,
-    squares: String,
-}
-impl CardJson {
-    fn to_card(&self) -> Card {
-        let seed = CardShape::new(&self.squares);
-        let shape = CardShape::trim(&seed);
-        Card::new(self.id, &self.name, self.cost, shape)
-    }
-}
-fn load_card_catalog(card_catalog_path: &str) -> serde_json::Result<Vec<Card>> {
-    let text = std::fs::read_to_string(card_catalog_path).unwrap();
-    let raw_cards = serde_json::from_str::<Vec<CardJson>>(&text).unwrap();
-    let mut cards = vec![];
-    for c in raw_cards {
-        cards.push(c.to_card());
+// `builtin:random`はプロセス内のbot、それ以外は外部プロセスのbotとして起動する。
+// botごとに対局の種から作った種を渡す。
+fn launch_agents(
+    bots: &[&BotConfig],
+    seed: u64,
+    config: &MatchConfig,
+) -> Result<Vec<Box<dyn Agent>>, String> {
+    let mut agents: Vec<Box<dyn Agent>> = vec![];
+    for (player_id, bot) in bots.iter().enumerate() {
+        let bot_seed = derive_seed(seed, player_id as u64);
+        if bot.command == "builtin:random" {
+            agents.push(Box::new(RandomAgent::new(&bot.command, game_rng(bot_seed))));
+            continue;
+        }
+        let mut command = Command::new(&bot.command);
+        command
+            .args(&bot.args)
+            .env(SEED_ENV_VAR, bot_seed.to_string());
+        let agent = ProcessAgent::spawn(command, config.time_limits())
+            .map_err(|why| format!("{}: {}", bot, why))?;
+        agents.push(Box::new(agent.with_stderr_limit(config.stderr_limit())));
     }
-    serde_json::Result::Ok(cards)
+    Ok(agents)
 }
-
-struct GameInfo {
-    winner: Option<usize>,
-    n_squares: Vec<usize>,
-}
-fn lunch_bots(commands: &[&str]) -> Vec<Child> {
-    let mut bot_processes = vec![];
-    for command in commands.iter() {
-        let bot_process = match Command::new(command.clone())
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-        {
-            Err(why) => panic!("couldn't spawn bot: {}", why),
-            Ok(process) => process,
-        };
-        bot_processes.push(bot_process);
+// 出力先の拡張子が`.json`ならJSONの棋譜、それ以外はテキストの棋譜にする
+fn format_kifu(
+    env: &Environment,
+    cards: &[Card],
+    field: &Field,
+    bots: &[&BotConfig],
+    record: &GameRecord,
+    path: Option<&str>,
+) -> String {
+    let is_json =
+        path.is_some_and(|path| Path::new(path).extension().is_some_and(|ext| ext == "json"));
+    if !is_json {
+        // 棋譜の先頭には種を記録する
+        return format_record(env, cards, &field.shape, record);
     }
-    bot_processes
-}
-fn read_names(bot_processes: &mut Vec<Child>) -> Vec<String> {
-    let mut names = vec![];
-    for (player_id, bot_process) in bot_processes.iter_mut().enumerate() {
-        let stdout = bot_process.stdout.as_mut().expect("");
-        let mut reader = BufReader::new(stdout);
-        let mut s = String::new();
-        match reader.read_line(&mut s) {
-            Err(why) => panic!("couldn't read player stdout: {}", why),
-            Ok(_) => debug!("player {} name: {}", player_id, s),
-        };
-        let name = s.trim().to_string();
-        names.push(name);
+    let mut kifu = Kifu::new(env, field, cards, record);
+    kifu.metadata.date = Some(format_date(SystemTime::now()));
+    kifu.metadata.judge_version = Some(env!("CARGO_PKG_VERSION").to_string());
+    for (info, bot) in kifu.metadata.bots.iter_mut().zip(bots.iter()) {
+        info.command = Some(bot.to_string());
+        info.version = bot.version.clone();
     }
-    names
+    kifu.to_json() + "\n"
 }
-fn format_initial_input(env: &Environment, cards: &[Card], field: &Field) -> String {
-    let cards_info = cards
-        .iter()
-        .map(|card| {
-            format!(
-                "{} {} {} {}\n{}",
-                card.id, card.cost, card.shape.height, card.shape.width, card.shape
-            )
-        })
-        .collect::<Vec<String>>()
-        .join("\n");
-
-    format!(
-        "{player_size} {deck_size} {hand_size} {max_turn} {is_deplicated_pick_enabled}\n{stage_size_y} {stage_size_x}\n{stage_shape}\n{number_of_cards}\n{cards_info}\n",
-        player_size = env.player_size,
-        deck_size = env.deck_size,
-        hand_size = env.hand_size,
-        max_turn = env.max_turn,
-        is_deplicated_pick_enabled = if env.is_deplicated_pick_enabled{"1"}else{"0"},
-        stage_size_y = field.shape.height,
-        stage_size_x = field.shape.width,
-        stage_shape = field.shape,
-        number_of_cards = cards.len(),
-        cards_info = cards_info
-    )
-}
-fn print_initial_input(bot_processes: &mut Vec<Child>, initial_input: &str) {
-    for bot_process in bot_processes.iter() {
-        let mut stdin = bot_process.stdin.as_ref().unwrap();
-
-        if let Err(why) = stdin.write_all(initial_input.as_bytes()) {
-            panic!("couldn't write to player stdin: {}", why);
+fn exec_game(
+    env: &Environment,
+    cards: &[Card],
+    field: &Field,
+    config: &MatchConfig,
+    policy: FailurePolicy,
+    seed: u64,
+    record_path: Option<&str>,
+) -> Result<GameRecord, String> {
+    let bots = config.bots.iter().collect::<Vec<&BotConfig>>();
+    // 同じ種と同じbotなら同じ棋譜になるよう、乱数はすべて種から作る
+    let mut agents = launch_agents(&bots, seed, config)
+        .map_err(|why| GameError::SpawnFailed(why).to_string())?;
+    let record = Match::run_with_policy(env, field, cards, &mut agents, seed, policy)
+        .map_err(|why| why.to_string())?;
+    drop(agents);
+    let kifu = format_kifu(env, cards, field, &bots, &record, record_path);
+    match record_path {
+        Some(path) => {
+            std::fs::write(path, kifu).map_err(|why| format!("couldn't write {}: {}", path, why))?
         }
-        stdin.flush().unwrap_or(());
-    }
-    print!("{}", initial_input); //末尾に改行が含まれる文字列であるため
-}
-fn read_decks(bot_processes: &mut Vec<Child>) -> Vec<Vec<usize>> {
-    let mut decks = vec![];
-    for (player_id, bot_process) in bot_processes.iter_mut().enumerate() {
-        let stdout = bot_process.stdout.as_mut().expect("");
-        let mut reader = BufReader::new(stdout);
-        let mut s = String::new();
-        match reader.read_line(&mut s) {
-            Err(why) => panic!("couldn't read player stdout: {}", why),
-            Ok(_) => debug!("player {} deck: {}", player_id, s),
-        };
-        let mut deck: Vec<usize> = s
-            .trim()
-            .split(' ')
-            .filter(|s| !s.is_empty())
-            .map(|s| {
-                s.parse::<usize>()
-                    .expect("botを完全に信用する。TODO:ここは入力の検証が必要。")
-            })
-            .collect();
-        println!(
-            "{}",
-            deck.iter()
-                .map(|v| v.to_string())
-                .collect::<Vec<String>>()
-                .join(" ")
-        );
-        decks.push(deck);
+        None => print!("{}", kifu),
     }
-    decks
+    Ok(record)
 }
-fn shuffle_and_mulligan(
+// `judge tournament --bot A --bot B ...`で複数のbotを対戦させ、対戦表を出力する。
+fn exec_tournament(
     env: &Environment,
-    bot_processes: &mut Vec<Child>,
-    decks: &Vec<Vec<usize>>,
-    rng: &mut ThreadRng,
-) -> Vec<Vec<usize>> {
-    let mut shuffled_decks = vec![];
-    for (player_id, bot_process) in bot_processes.iter_mut().enumerate() {
-        let mut deck = decks[player_id].clone();
-        deck.shuffle(rng);
-        // デッキの順番を記録する
-        println!(
-            "{}",
-            deck.iter()
-                .map(|card_id| { card_id.to_string() })
-                .collect::<Vec<String>>()
-                .join(" ")
-        );
-        shuffled_decks.push(deck);
+    cards: &[Card],
+    field: &Field,
+    config: &MatchConfig,
+    format: Format,
+    policy: FailurePolicy,
+    seed: u64,
+) -> Result<(), String> {
+    let bots = &config.bots;
+    let games_per_pairing = config.games.unwrap_or(10);
+    let n_threads = config
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    info!(
+        "format:{}\tgames_per_pairing:{}\tthreads:{}",
+        format, games_per_pairing, n_threads
+    );
 
-        let hands = format!(
-            "{}\n",
-            shuffled_decks[player_id][0..env.hand_size]
-                .iter()
-                .map(|v| v.to_string())
-                .collect::<Vec<String>>()
-                .join(" "),
-        );
-        let mut stdin = bot_process.stdin.as_ref().unwrap();
-        if let Err(why) = stdin.write_all(hands.as_bytes()) {
-            panic!("couldn't write to player stdin: {}", why);
+    let games = schedule(bots.len(), format, games_per_pairing, seed);
+    let records = run_games(env, field, cards, &games, n_threads, policy, |game| {
+        launch_agents(&game.seats.map(|bot| &bots[bot]), game.seed, config)
+            .map_err(GameError::SpawnFailed)
+    })
+    .map_err(|why| why.to_string())?;
+    for (game, record) in games.iter().zip(records.iter()) {
+        if let Some(forfeit) = &record.forfeit {
+            info!(
+                "game:{}\tforfeit:{}\treason:{}",
+                game.index, bots[game.seats[forfeit.player_id]], forfeit.reason
+            );
         }
-        stdin.flush().unwrap_or(());
-
-        let stdout = bot_process.stdout.as_mut().expect("");
-        let mut reader = BufReader::new(stdout);
-        let mut s = String::new();
-        match reader.read_line(&mut s) {
-            Err(why) => panic!("couldn't read player stdout: {}", why),
-            Ok(_) => debug!("player {} mulligan: {}", player_id, s),
-        };
-        let action = MulliganAction::from(s.as_str());
-        println!("{}", action);
-        if action == MulliganAction::Mulligan {
-            debug!("player {} mulliganed", player_id);
-            shuffled_decks[player_id].shuffle(rng);
+        if let Some(path) = config.record_path(game.index, games.len()) {
+            let seats = game.seats.map(|bot| &bots[bot]);
+            let kifu = format_kifu(env, cards, field, &seats, record, Some(&path));
+            std::fs::write(&path, kifu)
+                .map_err(|why| format!("couldn't write {}: {}", path, why))?;
         }
     }
-    shuffled_decks
+    let names = bots
+        .iter()
+        .map(|bot| bot.to_string())
+        .collect::<Vec<String>>();
+    print!("{}", Crosstable::new(&names, &games, &records));
+    Ok(())
 }
-fn format_turn_input(
-    field: &Field,
-    card_catalog: &HashMap<usize, &Card>,
-    state: &mut State,
-    player_id: usize,
-) -> String {
-    let action_candidates = state.generate_valid_actions(card_catalog, player_id);
-    debug!(
-        "turn:{}, player_id:{}, n_action:{}",
-        state.turn,
-        player_id,
-        action_candidates.len()
-    );
-    format!(
-        "{turn}\n{special_points}\n{stage_shape}\n{hands}\n{n_action}\n{action_candidates}\n",
-        turn = state.turn,
-        special_points = state
-            .players
-            .iter()
-            .map(|p| p.special_point.to_string())
-            .collect::<Vec<String>>()
-            .join(" "),
-        stage_shape = field.shape,
-        hands = state.players[player_id]
-            .hands
-            .iter()
-            .map(|v| v.to_string())
-            .collect::<Vec<String>>()
-            .join(" "),
-        n_action = action_candidates.len(),
-        action_candidates = action_candidates
-            .iter()
-            .map(|a| a.to_string())
-            .collect::<Vec<String>>()
-            .join("\n")
-    )
+// オプションの誤りは理由と使い方を表示して終了する
+fn usage_error(why: impl Display) -> ! {
+    eprintln!("judge: {}\n", why);
+    eprint!("{}", USAGE);
+    std::process::exit(2);
 }
-fn read_action(bot_process: &mut Child, player_id: usize) -> Action {
-    let stdout = bot_process.stdout.as_mut().expect("");
-    let mut reader = BufReader::new(stdout);
-    let mut s = String::new();
-    match reader.read_line(&mut s) {
-        Err(why) => panic!("couldn't read player stdout: {}", why),
-        Ok(_) => debug!("player {} action: {}", player_id, s),
-    };
-    Action::from(s.as_ref())
+// 対局を始められないときや棋譜を書けないときは理由を表示して終了する
+fn fatal(why: impl Display) -> ! {
+    eprintln!("judge: {}", why);
+    std::process::exit(1);
 }
-fn game_loop(
-    env: &Environment,
-    field: &Field,
-    card_catalog: &HashMap<usize, &Card>,
-    bot_processes: &mut Vec<Child>,
-    state: &mut State,
-) {
-    let mut actions = vec![];
-
-    for (player_id, bot_process) in bot_processes.iter_mut().enumerate() {
-        let turn_input = format_turn_input(field, card_catalog, state, player_id);
-
-        let mut stdin = bot_process.stdin.as_ref().unwrap();
-        if let Err(why) = stdin.write_all(turn_input.as_bytes()) {
-            panic!("couldn't write to player stdin: {}", why);
+fn main() {
+    // 2つのプログラムと情報の受け渡しを行いゲームを進めるプログラム
+    env_logger::init();
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", USAGE);
+        return;
+    }
+    let mut config = MatchConfig::from_args(&args).unwrap_or_else(|why| usage_error(why));
+    let env = config.environment().unwrap_or_else(|why| usage_error(why));
+    let policy = config
+        .failure_policy()
+        .unwrap_or_else(|why| usage_error(why));
+    let format = config.format().unwrap_or_else(|why| usage_error(why));
+    let is_tournament = args.get(1).map(String::as_str) == Some("tournament");
+    if is_tournament {
+        if env.player_size != 2 {
+            usage_error("tournaments are only for 2 players");
+        }
+        if config.bots.len() < 2 {
+            usage_error("a tournament needs at least 2 --bot");
+        }
+    } else {
+        if config.bots.is_empty() {
+            config.bots = vec![BotConfig::parse("target/release/bot").unwrap(); env.player_size];
+        }
+        if config.bots.len() != env.player_size {
+            usage_error(format!(
+                "{} --bot for {} players",
+                config.bots.len(),
+                env.player_size
+            ));
         }
-        stdin.flush().unwrap_or(());
-
-        let action = read_action(bot_process, player_id);
-        println!("{}", action);
-        actions.push(action);
     }
+    // 種を指定しない場合はランダムに決める
+    let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    info!("seed:{}", seed);
 
-    state.apply(env, card_catalog, &actions);
-}
-
-fn exec_game(env: &Environment, cards: &[Card], field: &Field, commands: &[&str]) -> GameInfo {
-    let mut rng = rand::thread_rng();
-    let mut bot_processes = lunch_bots(commands);
-    let names = read_names(&mut bot_processes);
-
-    let initial_input = format_initial_input(env, cards, field);
-    print_initial_input(&mut bot_processes, &initial_input);
-
-    let mut card_catalog = HashMap::new();
-    for card in cards {
-        card_catalog.insert(card.id, card);
+    let cards = load_card_catalog(config.card_catalog()).unwrap_or_else(|why| fatal(why));
+    let field = match &config.field {
+        Some(key) => {
+            let path = config.field_catalog();
+            let fields = load_field_catalog(path).unwrap_or_else(|why| fatal(why));
+            find_field(&fields, key)
+                .unwrap_or_else(|| fatal(format!("field {} is not in {}", key, path)))
+                .clone()
+        }
+        None => Field::default(),
+    };
+    info!("field:{}", field.name);
+    // botを起動する前に、フィールドにプレイヤー全員のスペシャルマスがあるか確かめる
+    check_field(&env, &field).unwrap_or_else(|why| fatal(format!("field {}: {}", field.name, why)));
+    if is_tournament {
+        exec_tournament(&env, &cards, &field, &config, format, policy, seed)
+            .unwrap_or_else(|why| fatal(why));
+        return;
     }
 
-    println!("{}", names.join("\n"));
-
-    let decks = read_decks(&mut bot_processes);
-    let shuffled_decks = shuffle_and_mulligan(env, &mut bot_processes, &decks, &mut rng);
-    // デッキの順番を記録する
-    for deck in shuffled_decks.iter() {
-        println!(
-            "{}",
-            deck.iter()
-                .map(|card_id| { card_id.to_string() })
-                .collect::<Vec<String>>()
-                .join(" ")
+    // 2局目以降は種から作った種で対局する
+    let n_games = config.games.unwrap_or(1);
+    for game in 0..n_games {
+        let game_seed = if game == 0 {
+            seed
+        } else {
+            derive_seed(seed, game as u64)
+        };
+        let result = exec_game(
+            &env,
+            &cards,
+            &field,
+            &config,
+            policy,
+            game_seed,
+            config.record_path(game, n_games).as_deref(),
         )
-    }
-
-    let mut state = State::new(env, &card_catalog, field, &shuffled_decks);
-    // 毎ターンの繰り返し処理
-    while !state.is_done(env) {
-        game_loop(env, field, &card_catalog, &mut bot_processes, &mut state)
-    }
-    drop(bot_processes);
-
-    let winner = if state.is_win(env, 0) {
-        info!("Player0(Yellow) won");
-        Some(0)
-    } else if state.is_win(env, 1) {
-        info!("Player1(Blue) won");
-        Some(1)
-    } else {
-        info!("DRAW");
-        None
-    };
-    info!(
-        "{} : {}",
-        state.field.count_player(0),
-        state.field.count_player(1),
-    );
-    info!("\n{}", state.field.to_string());
-
-    GameInfo {
-        winner,
-        n_squares: vec![state.field.count_player(0), state.field.count_player(1)],
+        .unwrap_or_else(|why| fatal(why));
+        // 結果はスクリプトで読めるようにタブ区切りで1回だけ出す
+        for failure in result.failures.iter() {
+            info!(
+                "pass_from:{}\tturn:{}\treason:{}",
+                failure.player_id, failure.turn, failure.reason
+            );
+        }
+        if let Some(forfeit) = &result.forfeit {
+            info!("forfeit:{}\treason:{}", forfeit.player_id, forfeit.reason);
+        }
+        let squares = result
+            .scores
+            .iter()
+            .zip(result.ranking.iter())
+            .enumerate()
+            .map(|(player_id, (n, rank))| {
+                format!(
+                    "player{id}_square:{n}\tplayer{id}_rank:{rank}",
+                    id = player_id,
+                    n = n,
+                    rank = rank
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\t");
+        match result.winner {
+            Some(winner) => info!("winner:{}\t{}", winner, squares),
+            None => info!("winner:none\t{}", squares),
+        }
     }
 }
-fn main() {
-    // 2つのプログラムと情報の受け渡しを行いゲームを進めるプログラム
-    env_logger::init();
 
-    // let env = Environment::new(2, 15, 4, 12, false);
-    let env = Environment::new(2, 20, 4, 17, true);
-    let cards =
-        load_card_catalog("resources/card_catalog_sample.json").expect("JSON読み込みはうまくいく");
-    // TODO: 別のフィールドも使えるようにする
-    let field = Field::default();
-    let result = exec_game(
-        &env,
-        &cards,
-        &field,
-        &["target/release/bot", "target/release/bot"],
-    );
-    if let Some(winner) = result.winner {
-        info!(
-            "winner:{}\tplayer0_square:{}\tplayer1_square:{}",
-            winner, result.n_squares[0], result.n_squares[1]
-        );
-    } else {
-        info!(
-            "winner:none\tplayer0_square:{}\tplayer1_square:{}",
-            result.n_squares[0], result.n_squares[1]
-        );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tableturfbattle::catalog::{parse_card_catalog, parse_field_catalog};
+
+    #[test]
+    fn test_multi_player() {
+        let cards =
+            parse_card_catalog(include_str!("../../resources/card_catalog_sample.json")).unwrap();
+        let fields =
+            parse_field_catalog(include_str!("../../resources/field_catalog.json")).unwrap();
+        for (player_size, field) in [(3, "みつどもえプラザ"), (4, "よつかどスクエア")]
+        {
+            let args = format!(
+                "judge --player-size {} --field {}{}",
+                player_size,
+                field,
+                " --bot builtin:random".repeat(player_size)
+            )
+            .split(' ')
+            .map(|s| s.to_string())
+            .collect::<Vec<String>>();
+            let config = MatchConfig::from_args(&args).unwrap();
+            let env = config.environment().unwrap();
+            let field = find_field(&fields, config.field.as_ref().unwrap()).unwrap();
+            let bots = config.bots.iter().collect::<Vec<&BotConfig>>();
+            let mut agents = launch_agents(&bots, 1, &config).unwrap();
+            let record = Match::run(&env, field, &cards, &mut agents, 1).unwrap();
+            assert_eq!(record.forfeit, None);
+            assert_eq!(record.turns.len(), env.max_turn);
+            assert_eq!(record.scores.len(), player_size);
+            assert_eq!(record.ranking.len(), player_size);
+        }
     }
 }
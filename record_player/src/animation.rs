@@ -0,0 +1,163 @@
+use crate::frame::{palette_rgb, Image};
+use std::{
+    borrow::Cow,
+    fmt::{Display, Formatter},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+/// アニメーションの形式
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Format {
+    Gif,
+    Apng,
+}
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Format::Gif => write!(f, "gif"),
+            Format::Apng => write!(f, "apng"),
+        }
+    }
+}
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gif" => Ok(Format::Gif),
+            "apng" | "png" => Ok(Format::Apng),
+            _ => Err(format!("unknown animation format: {}", s)),
+        }
+    }
+}
+impl Format {
+    /// 出力先の拡張子から形式を決める
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .ok_or_else(|| format!("no extension in {}", path.display()))?;
+        ext.to_ascii_lowercase().parse()
+    }
+}
+
+/// 同じ大きさの画像を`delay`ごとに切り替え、繰り返し再生するアニメーションを書き出す
+pub fn write_animation(
+    path: &Path,
+    format: Format,
+    frames: &[Image],
+    delay: Duration,
+) -> io::Result<()> {
+    // 出力先のディレクトリが無ければ作る
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    match format {
+        Format::Gif => encode_gif(&mut out, frames, delay)?,
+        Format::Apng => encode_apng(&mut out, frames, delay)?,
+    }
+    out.flush()
+}
+
+fn encode_gif(out: &mut impl Write, frames: &[Image], delay: Duration) -> io::Result<()> {
+    let Some(first) = frames.first() else {
+        return Ok(());
+    };
+    let size = |n: usize| {
+        u16::try_from(n).map_err(|_| io::Error::other(format!("too large for GIF: {}", n)))
+    };
+    let (width, height) = (size(first.width)?, size(first.height)?);
+    let palette = palette_rgb();
+    let mut encoder = gif::Encoder::new(out, width, height, &palette).map_err(io::Error::other)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(io::Error::other)?;
+    // GIFの表示時間は1/100秒単位
+    let delay = u16::try_from(delay.as_millis() / 10).unwrap_or(u16::MAX);
+    for image in frames.iter() {
+        let frame = gif::Frame {
+            width,
+            height,
+            delay,
+            buffer: Cow::Borrowed(&image.pixels),
+            ..Default::default()
+        };
+        encoder.write_frame(&frame).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+fn encode_apng(out: &mut impl Write, frames: &[Image], delay: Duration) -> io::Result<()> {
+    let Some(first) = frames.first() else {
+        return Ok(());
+    };
+    let size = |n: usize| {
+        u32::try_from(n).map_err(|_| io::Error::other(format!("too large for PNG: {}", n)))
+    };
+    let mut encoder = png::Encoder::new(out, size(first.width)?, size(first.height)?);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette_rgb());
+    // 0回は無限に繰り返す
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(io::Error::other)?;
+    let delay = u16::try_from(delay.as_millis()).unwrap_or(u16::MAX);
+    encoder
+        .set_frame_delay(delay, 1000)
+        .map_err(io::Error::other)?;
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    for image in frames.iter() {
+        writer
+            .write_image_data(&image.pixels)
+            .map_err(io::Error::other)?;
+    }
+    writer.finish().map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let frames = vec![
+            Image {
+                width: 3,
+                height: 2,
+                pixels: vec![0, 1, 2, 3, 4, 5],
+            },
+            Image {
+                width: 3,
+                height: 2,
+                pixels: vec![5, 4, 3, 2, 1, 0],
+            },
+        ];
+        let mut gif = vec![];
+        encode_gif(&mut gif, &frames, Duration::from_millis(500)).unwrap();
+        let mut decoder = gif::DecodeOptions::new();
+        decoder.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = decoder.read_info(gif.as_slice()).unwrap();
+        for image in frames.iter() {
+            let frame = decoder.read_next_frame().unwrap().unwrap();
+            assert_eq!(frame.delay, 50);
+            assert_eq!(frame.buffer.as_ref(), image.pixels.as_slice());
+        }
+        assert!(decoder.read_next_frame().unwrap().is_none());
+
+        let mut apng = vec![];
+        encode_apng(&mut apng, &frames, Duration::from_millis(500)).unwrap();
+        let decoder = png::Decoder::new(apng.as_slice());
+        let reader = decoder.read_info().unwrap();
+        let control = reader.info().animation_control.unwrap();
+        assert_eq!(control.num_frames, 2);
+
+        assert_eq!(Format::from_path(Path::new("a/b.GIF")), Ok(Format::Gif));
+        assert_eq!(Format::from_path(Path::new("b.png")), Ok(Format::Apng));
+        assert!(Format::from_path(Path::new("b.webp")).is_err());
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use svg::node::element::Rectangle;
+use tableturfbattle::{
+    Card, CardId, CardSquareType, Direction, FieldSquareType, State, MAX_PLAYER_SIZE, PLAYER_CHARS,
+};
+
+// プレイヤーごとの[通常マス, スペシャルマス]の色
+const COLORS: [[&str; 2]; MAX_PLAYER_SIZE] = [
+    ["yellow", "orange"],
+    ["blue", "aqua"],
+    ["green", "lime"],
+    ["purple", "violet"],
+];
+const BLOCK_COLOR: &str = "gray";
+const EMPTY_COLOR: &str = "white";
+const TEXT_COLOR: &str = "black";
+/// 使う色の名前とRGB。この順番がGIFやAPNGのパレットになる
+pub const PALETTE: [(&str, [u8; 3]); 11] = [
+    ("white", [255, 255, 255]),
+    ("black", [0, 0, 0]),
+    ("gray", [128, 128, 128]),
+    ("yellow", [255, 255, 0]),
+    ("orange", [255, 165, 0]),
+    ("blue", [0, 0, 255]),
+    ("aqua", [0, 255, 255]),
+    ("green", [0, 128, 0]),
+    ("lime", [0, 255, 0]),
+    ("purple", [128, 0, 128]),
+    ("violet", [238, 130, 238]),
+];
+fn palette_index(color: &str) -> u8 {
+    PALETTE
+        .iter()
+        .position(|(name, _)| *name == color)
+        .expect("パレットにある色だけを使う") as u8
+}
+/// パレットをRGBの順に並べたもの
+pub fn palette_rgb() -> Vec<u8> {
+    PALETTE.iter().flat_map(|(_, rgb)| *rgb).collect()
+}
+
+fn field_color(square: &FieldSquareType) -> &'static str {
+    match &square {
+        FieldSquareType::Block => BLOCK_COLOR,
+        FieldSquareType::Empty => EMPTY_COLOR,
+        FieldSquareType::Special {
+            player_id,
+            activeted: _,
+        } => COLORS[*player_id][1],
+        FieldSquareType::Colored { player_id } => COLORS[*player_id][0],
+    }
+}
+fn hand_color(square: &CardSquareType, player_id: usize) -> &'static str {
+    match &square {
+        CardSquareType::Empty => EMPTY_COLOR,
+        CardSquareType::Special => COLORS[player_id][1],
+        CardSquareType::Colored => COLORS[player_id][0],
+    }
+}
+
+struct Rect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    fill: &'static str,
+}
+// 左下が(x, y)の文字列
+struct Caption {
+    x: usize,
+    y: usize,
+    font_size: usize,
+    text: String,
+}
+/// 1ターン分の盤面と手札の絵。SVGにもラスタ画像にもできる
+pub struct Frame {
+    width: usize,
+    height: usize,
+    rects: Vec<Rect>,
+    caption: Caption,
+}
+impl Frame {
+    pub fn new(cards: &HashMap<CardId, &Card>, state: &State, turn: usize) -> Self {
+        // とりあえず公式のサイズ感で崩れないようにする
+        let cell_size = 30;
+        let font_size = 60;
+        let padding = cell_size;
+
+        let card_height = cell_size
+            * cards
+                .values()
+                .map(|c| c.orientation(Direction::Up).shape.height)
+                .max()
+                .expect("大丈夫なはず");
+        let card_width = cell_size
+            * cards
+                .values()
+                .map(|c| c.orientation(Direction::Up).shape.width)
+                .max()
+                .expect("大丈夫なはず");
+        let padding_hand_zone_width = padding * 2 + card_width * 2;
+
+        // hand zone
+        let hand_zone_height = padding * 2
+            + card_height * 2 * state.players.len()
+            + padding * (2 * state.players.len() - 1);
+        let hand_zone_width = padding * 2 + card_width * 2 + padding;
+        // filed
+        let field_height = (state.field.height) * cell_size + padding * 2;
+        let field_width = (state.field.width) * cell_size + padding * 2;
+
+        let width = hand_zone_width + field_width;
+        let height = std::cmp::max(hand_zone_height, field_height);
+
+        // 背景色描画
+        let mut rects = vec![Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+            fill: BLOCK_COLOR,
+        }];
+        // handsを描画
+        for (player_id, player) in state.players.iter().enumerate() {
+            for (card_index, card_id) in player.hands.iter().enumerate() {
+                let base_y = padding
+                    + (card_index / 2 * (padding + card_height))
+                    + player_id * 2 * (padding + card_height);
+                let base_x = padding + card_index % 2 * (card_width + padding);
+                // 背景
+                rects.push(Rect {
+                    x: base_x,
+                    y: base_y,
+                    width: card_width,
+                    height: card_height,
+                    fill: EMPTY_COLOR,
+                });
+
+                // 本体
+                let shape = &cards
+                    .get(card_id)
+                    .expect("ある")
+                    .orientation(Direction::Up)
+                    .shape;
+                for i in 0..shape.height {
+                    for j in 0..shape.width {
+                        rects.push(Rect {
+                            x: base_x + j * cell_size,
+                            y: base_y + i * cell_size,
+                            width: cell_size,
+                            height: cell_size,
+                            fill: hand_color(&shape.squares[i][j], player_id),
+                        });
+                    }
+                }
+            }
+        }
+        // fieldを描画
+        for i in 0..state.field.height {
+            for j in 0..state.field.width {
+                rects.push(Rect {
+                    x: padding + j * cell_size + padding_hand_zone_width,
+                    y: padding + i * cell_size,
+                    width: cell_size,
+                    height: cell_size,
+                    fill: field_color(&state.field.squares[i][j]),
+                });
+            }
+        }
+        // special pointを描画
+        let caption = Caption {
+            x: hand_zone_width,
+            y: field_height + font_size,
+            font_size,
+            text: format!(
+                "t{},{}",
+                turn,
+                state
+                    .players
+                    .iter()
+                    .enumerate()
+                    .map(|(player_id, player)| format!(
+                        "{}{}",
+                        PLAYER_CHARS[player_id], player.special_point
+                    ))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+        };
+        Frame {
+            width,
+            height,
+            rects,
+            caption,
+        }
+    }
+
+    pub fn to_svg(&self) -> svg::Document {
+        let mut doc = svg::Document::new()
+            .set("id", "visualizer")
+            .set("viewBox", (0, 0, self.width, self.height))
+            .set("width", self.width)
+            .set("height", self.height);
+        for rect in self.rects.iter() {
+            doc = doc.add(
+                Rectangle::new()
+                    .set("x", rect.x)
+                    .set("y", rect.y)
+                    .set("width", rect.width)
+                    .set("height", rect.height)
+                    .set("fill", rect.fill),
+            );
+        }
+        doc.add(
+            svg::node::element::Text::new()
+                .set("x", self.caption.x)
+                .set("y", self.caption.y as f64)
+                .set("font-size", self.caption.font_size)
+                .set("text-anchor", "left")
+                .add(svg::node::Text::new(self.caption.text.clone())),
+        )
+    }
+
+    /// `scale`倍の大きさのパレット画像にする
+    pub fn rasterize(&self, scale: f64) -> Image {
+        let to_pixel = |n: usize| (n as f64 * scale).round() as usize;
+        let mut image = Image::new(to_pixel(self.width).max(1), to_pixel(self.height).max(1));
+        for rect in self.rects.iter() {
+            image.fill(
+                to_pixel(rect.x),
+                to_pixel(rect.y),
+                to_pixel(rect.x + rect.width),
+                to_pixel(rect.y + rect.height),
+                palette_index(rect.fill),
+            );
+        }
+        // 文字は3x5ドットのフォントで描く。フォントの大きさの5/7を文字の高さにする
+        let dot = (self.caption.font_size as f64 * scale / 7.0).max(1.0);
+        let bottom = self.caption.y as f64 * scale;
+        let mut left = self.caption.x as f64 * scale;
+        for c in self.caption.text.chars() {
+            for (i, row) in glyph(c).iter().enumerate() {
+                for j in 0..3 {
+                    if row >> (2 - j) & 1 == 0 {
+                        continue;
+                    }
+                    let x = left + j as f64 * dot;
+                    let y = bottom - (5 - i) as f64 * dot;
+                    image.fill(
+                        x.round() as usize,
+                        y.round() as usize,
+                        (x + dot).round() as usize,
+                        (y + dot).round() as usize,
+                        palette_index(TEXT_COLOR),
+                    );
+                }
+            }
+            left += dot * 4.0;
+        }
+        image
+    }
+}
+
+// 3x5ドットの字形。各行の下位3ビットが左から右のドット。数字と`PLAYER_CHARS`と区切りだけ描ける
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'b' => [0b100, 0b110, 0b101, 0b101, 0b110],
+        'g' => [0b011, 0b101, 0b011, 0b001, 0b110],
+        'p' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'y' => [0b101, 0b101, 0b011, 0b001, 0b110],
+        't' => [0b010, 0b111, 0b010, 0b010, 0b011],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        _ => [0; 5],
+    }
+}
+
+/// `PALETTE`の番号を画素に持つ画像
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+impl Image {
+    fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+    // [x0, x1) x [y0, y1)を塗る。画像からはみ出した部分は描かない
+    fn fill(&mut self, x0: usize, y0: usize, x1: usize, y1: usize, color: u8) {
+        let (x1, y1) = (x1.min(self.width), y1.min(self.height));
+        for y in y0..y1 {
+            for x in x0.min(x1)..x1 {
+                self.pixels[y * self.width + x] = color;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rasterize() {
+        let frame = Frame {
+            width: 4,
+            height: 2,
+            rects: vec![
+                Rect {
+                    x: 0,
+                    y: 0,
+                    width: 4,
+                    height: 2,
+                    fill: "gray",
+                },
+                Rect {
+                    x: 2,
+                    y: 1,
+                    width: 4,
+                    height: 4,
+                    fill: "blue",
+                },
+            ],
+            caption: Caption {
+                x: 0,
+                y: 0,
+                font_size: 7,
+                text: String::new(),
+            },
+        };
+        assert_eq!(frame.rasterize(1.0).pixels, vec![2, 2, 2, 2, 2, 2, 5, 5],);
+        let image = frame.rasterize(2.0);
+        assert_eq!((image.width, image.height), (8, 4));
+        assert_eq!(image.pixels[2 * 8 + 3..2 * 8 + 5], [2, 5]);
+        assert_eq!(palette_rgb().len(), PALETTE.len() * 3);
+    }
+}
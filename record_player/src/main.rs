@@ -1,250 +1,115 @@
-use std::collections::HashMap;
-use std::io;
-use svg::node::{element::Rectangle, Text};
-use tableturfbattle::{
-    parse_input, read_line,
-    Action, Card, CardId, CardShape, CardSquareType, Environment, Field, FieldShape,
-    FieldSquareType, MulliganAction, State,
-};
-fn main() {
-    let chunks = read_line!();
-    let env = Environment {
-        player_size: parse_input!(chunks[0], usize),
-        deck_size: parse_input!(chunks[1], usize),
-        hand_size: parse_input!(chunks[2], usize),
-        max_turn: parse_input!(chunks[3], usize),
-        is_deplicated_pick_enabled: parse_input!(chunks[4], usize) == 1,
-    };
-    let chunks = read_line!();
-    let field_y = parse_input!(chunks[0], usize);
-    let _field_x = parse_input!(chunks[1], usize);
-    let mut rows = vec![];
-    for _ in 0..field_y {
-        rows.push(read_line!()[0].to_owned());
-    }
-
-    let field = Field {
-        id: 1,
-        name: "unknown".to_string(),
-        shape: FieldShape::new(&rows.join("\n")),
-    };
-
-    let chunks = read_line!();
-    let n_cards = parse_input!(chunks[0], usize);
-    let mut cards = vec![];
-    for _ in 0..n_cards {
-        let chunks = read_line!();
-        let card_id = parse_input!(chunks[0], usize);
-        let card_cost = parse_input!(chunks[1], usize);
-        let card_size_y = parse_input!(chunks[2], usize);
-        let _card_size_x = parse_input!(chunks[3], usize);
-        let mut rows = vec![];
-        for _ in 0..card_size_y {
-            rows.push(read_line!()[0].to_owned());
-        }
-        let shape = CardShape::new(&rows.join("\n"));
-        cards.push(Card::new(card_id, "unknown", card_cost, shape));
-    }
+mod animation;
+mod frame;
+mod options;
 
-    let mut player_names = vec![];
-    for _ in 0..env.player_size {
-        let chunks = read_line!();
-        player_names.push(chunks[0].to_owned());
-    }
+use animation::write_animation;
+use frame::Frame;
+use options::{Options, USAGE};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, Read};
+use tableturfbattle::{kifu::parse_kifu, Card, CardId, State};
 
-    // selected deck
-    for _ in 0..env.player_size {
-        let chunks = read_line!();
-        // player_names.push(chunks[0].to_owned());
+// オプションの誤りは理由と使い方を表示して終了する
+fn usage_error(why: impl Display) -> ! {
+    eprintln!("record_player: {}\n", why);
+    eprint!("{}", USAGE);
+    std::process::exit(2);
+}
+// 棋譜を読めないときや書き出せないときは理由を表示して終了する
+fn fatal(why: impl Display) -> ! {
+    eprintln!("record_player: {}", why);
+    std::process::exit(1);
+}
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", USAGE);
+        return;
     }
-    let mut decks = vec![];
-    for _ in 0..env.player_size {
-        // shuffled deck
-        let chunks = read_line!();
+    let options = Options::from_args(&args).unwrap_or_else(|why| usage_error(why));
 
-        // mulligan_action
-        let chunks = read_line!();
-        let _mulligan_action = MulliganAction::from(chunks[0].as_str());
+    // JSONの棋譜もテキストの棋譜も読める
+    let mut text = String::new();
+    io::stdin()
+        .read_to_string(&mut text)
+        .unwrap_or_else(|why| fatal(format!("couldn't read the kifu: {}", why)));
+    let kifu = parse_kifu(&text).unwrap_or_else(|why| fatal(why));
+    // `--json`ではテキストの棋譜をJSONの棋譜に変換して出力するだけにする
+    if options.json {
+        println!("{}", kifu.to_json());
+        return;
     }
-    for _ in 0..env.player_size {
-        // shuffled deck
-        let chunks = read_line!();
-        decks.push(
-            chunks
-                .iter()
-                .map(|v| v.parse::<usize>().expect("信頼する"))
-                .collect::<Vec<usize>>(),
-        );
+    if let Some(seed) = kifu.metadata.seed {
+        eprintln!("seed:{}", seed);
     }
+    let env = kifu.environment().unwrap_or_else(|why| fatal(why));
+    let field = kifu.field().unwrap_or_else(|why| fatal(why));
+    let cards = kifu.cards().unwrap_or_else(|why| fatal(why));
     let mut card_catalog = HashMap::new();
     for card in cards.iter() {
         card_catalog.insert(card.id, card);
     }
-    let mut state = State::new(&env, &card_catalog, &field, &decks);
+    // judgeと同じ手順で記録された山札の順番を配る
+    let mut state = kifu
+        .start(&env, &card_catalog, &field)
+        .unwrap_or_else(|why| fatal(why));
 
-    for turn in 1..=env.max_turn {
-        generate_svg(&env, &card_catalog, &state, turn);
-        let mut actions = vec![];
-        for _ in 0..env.player_size {
-            let chunks = read_line!();
-            actions.push(Action::from(chunks.join(" ").as_str()));
+    // 反則で終わったターンは盤面が変わらないので、適用できなかったターンで止める
+    let mut frames = vec![];
+    let mut played = 0;
+    for record in kifu.turns.iter() {
+        let mut next = state.clone();
+        match next.apply(&env, &card_catalog, &record.actions) {
+            Ok(outcome) => {
+                frames.push(draw(&card_catalog, &state, played + 1));
+                eprintln!("{}", outcome);
+                state = next;
+                played += 1;
+            }
+            Err(why) => {
+                eprintln!("turn {}: {}", played + 1, why);
+                break;
+            }
         }
-        state.apply(&env, &card_catalog, &actions);
     }
-    generate_svg(&env, &card_catalog, &state, env.max_turn);
-}
-
-fn rect(x: i32, y: i32, w: i32, h: i32, fill: &str) -> Rectangle {
-    Rectangle::new()
-        .set("x", x)
-        .set("y", y)
-        .set("width", w)
-        .set("height", h)
-        .set("fill", fill)
-}
-const COLORS: [[&str; 2]; 2] = [["yellow", "orange"], ["blue", "aqua"]];
-const BLOCK_COLOR: &str = "gray";
-const EMPTY_COLOR: &str = "white";
-fn field_color(square: &FieldSquareType) -> &'static str {
-    match &square {
-        FieldSquareType::Block => BLOCK_COLOR,
-        FieldSquareType::Empty => EMPTY_COLOR,
-        FieldSquareType::Special {
-            player_id,
-            activeted,
-        } => match player_id {
-            0 => COLORS[0][1],
-            1 => COLORS[1][1],
-            _ => EMPTY_COLOR,
-        },
-        FieldSquareType::Colored { player_id } => match player_id {
-            0 => COLORS[0][0],
-            1 => COLORS[1][0],
-            _ => EMPTY_COLOR,
-        },
+    frames.push(draw(&card_catalog, &state, played));
+    if let Some(dir) = &options.svg_dir {
+        std::fs::create_dir_all(dir)
+            .unwrap_or_else(|why| fatal(format!("couldn't create {}: {}", dir.display(), why)));
+        for (index, frame) in (1..).zip(frames.iter()) {
+            let path = dir.join(format!("TableturfBattle_{:04}.svg", index));
+            std::fs::write(&path, frame.to_svg().to_string())
+                .unwrap_or_else(|why| fatal(format!("couldn't write {}: {}", path.display(), why)));
+        }
     }
-}
-fn hand_color(square: &CardSquareType, player_id: usize) -> &'static str {
-    match &square {
-        CardSquareType::Empty => EMPTY_COLOR,
-        CardSquareType::Special => match player_id {
-            0 => COLORS[0][1],
-            1 => COLORS[1][1],
-            _ => EMPTY_COLOR,
-        },
-        CardSquareType::Colored => match player_id {
-            0 => COLORS[0][0],
-            1 => COLORS[1][0],
-            _ => EMPTY_COLOR,
+
+    let images = frames
+        .iter()
+        .map(|frame| frame.rasterize(options.scale))
+        .collect::<Vec<_>>();
+    write_animation(&options.output, options.format, &images, options.delay).unwrap_or_else(
+        |why| {
+            fatal(format!(
+                "couldn't write {}: {}",
+                options.output.display(),
+                why
+            ))
         },
-    }
+    );
+    eprintln!(
+        "{} frames -> {} ({})",
+        images.len(),
+        options.output.display(),
+        options.format
+    );
 }
-pub fn generate_svg(env: &Environment, cards: &HashMap<CardId, &Card>, state: &State, turn: usize) {
+
+// 盤面をログに出して1ターン分の絵にする
+fn draw(cards: &HashMap<CardId, &Card>, state: &State, turn: usize) -> Frame {
     eprintln!("turn:{}", turn);
     for row in state.field.squares.iter() {
         eprintln!("{:?}", row);
     }
-    // とりあえず公式のサイズ感で崩れないようにする
-    let cell_size = 30;
-    let font_size = 60;
-    let padding = cell_size;
-
-    let card_height = cell_size
-        * cards
-            .values()
-            .map(|c| c.shape.height)
-            .max()
-            .expect("大丈夫なはず");
-    let card_width = cell_size
-        * cards
-            .values()
-            .map(|c| c.shape.width)
-            .max()
-            .expect("大丈夫なはず");
-    let padding_hand_zone_width = padding * 2 + card_width * 2;
-
-    // hand zone
-    let hand_zone_height = padding * 2 + card_height * 4 + padding * 3;
-    let hand_zone_width = padding * 2 + card_width * 2 + padding;
-    // filed
-    let field_height = (state.field.height) * cell_size + padding * 2;
-    let field_width = (state.field.width) * cell_size + padding * 2;
-
-    let canvas_h = std::cmp::max(hand_zone_height, field_height);
-    let canvas_w = hand_zone_width + field_width;
-
-    // 画像サイズ設定
-    let mut doc = svg::Document::new()
-        .set("id", "visualizer")
-        .set("viewBox", (0, 0, canvas_w, canvas_h))
-        .set("width", canvas_w)
-        .set("height", canvas_h);
-    // 背景色描画
-    doc = doc.add(rect(0, 0, canvas_w as i32, canvas_h as i32, BLOCK_COLOR));
-
-    // handsを描画
-    for (player_id, player) in state.players.iter().enumerate() {
-        for (card_index, card_id) in player.hands.iter().enumerate() {
-            let base_y = padding
-                + (card_index / 2 * (padding + card_height))
-                + player_id * 2 * (padding + card_height);
-            let base_x = padding + card_index % 2 * (card_width + padding);
-            // 背景
-            doc = doc.add(rect(
-                base_x as i32,
-                base_y as i32,
-                card_width as i32,
-                card_height as i32,
-                EMPTY_COLOR,
-            ));
-
-            // 本体
-            let card = cards.get(card_id).expect("ある");
-            for i in 0..card.shape.height {
-                for j in 0..card.shape.width {
-                    let y = base_y + i * cell_size;
-                    let x = base_x + j * cell_size;
-                    let color = hand_color(&card.shape.squares[i][j], player_id);
-                    doc = doc.add(rect(
-                        x as i32,
-                        y as i32,
-                        cell_size as i32,
-                        cell_size as i32,
-                        color,
-                    ));
-                }
-            }
-        }
-    }
-    // fieldを描画
-    for i in 0..state.field.height {
-        for j in 0..state.field.width {
-            let y = padding + i * cell_size;
-            let x = padding + j * cell_size + padding_hand_zone_width;
-            let color = field_color(&state.field.squares[i][j]);
-            doc = doc.add(rect(
-                x as i32,
-                y as i32,
-                cell_size as i32,
-                cell_size as i32,
-                color,
-            ));
-        }
-    }
-    // special pointを描画
-    doc = doc.add(
-        svg::node::element::Text::new()
-            .set("x", hand_zone_width)
-            .set("y", field_height as f64 + font_size as f64)
-            .set("font-size", font_size)
-            .set("text-anchor", "left")
-            .add(svg::node::Text::new(format!(
-                "t{},y{},b{}",
-                turn, state.players[0].special_point, state.players[1].special_point
-            ))),
-    );
-
-    // ファイルに書き出し
-    let filename = format!("tmp/TableturfBattle_{:04}.svg", turn);
-    std::fs::write(filename, &doc.to_string()).unwrap();
+    Frame::new(cards, state, turn)
 }
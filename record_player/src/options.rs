@@ -0,0 +1,124 @@
+use crate::animation::Format;
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+pub const USAGE: &str = "\
+usage: record_player [options] < kifu
+
+  --output <path>    アニメーションの出力先。拡張子が.gifならGIF、.pngか.apngならAPNG（既定: TableturfBattle.gif）
+  --delay <ms>       1ターンの表示時間（既定: 500）
+  --scale <x>        SVGの何倍の大きさで描くか（既定: 0.5）
+  --svg-dir <dir>    ターンごとのSVGも書き出すディレクトリ
+  --json             描かずに、棋譜をJSONの棋譜に変換して出力する
+";
+// 値を取るオプション。`USAGE`に並べたものと同じ
+const OPTIONS: [&str; 4] = ["--output", "--delay", "--scale", "--svg-dir"];
+// 値を取らないオプション
+const FLAGS: [&str; 1] = ["--json"];
+
+/// コマンドライン引数で選んだ出力の設定
+#[derive(PartialEq, Debug, Clone)]
+pub struct Options {
+    pub output: PathBuf,
+    pub format: Format,
+    pub delay: Duration,
+    pub scale: f64,
+    pub svg_dir: Option<PathBuf>,
+    pub json: bool,
+}
+impl Options {
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let args = parse_args(args)?;
+        let args = args.as_slice();
+        let output = parsed(args, "--output")?.unwrap_or(PathBuf::from("TableturfBattle.gif"));
+        let format = Format::from_path(&output)?;
+        let scale = parsed(args, "--scale")?.unwrap_or(0.5);
+        if !(scale > 0.0 && f64::is_finite(scale)) {
+            return Err(format!("--scale must be positive but was {}", scale));
+        }
+        Ok(Self {
+            output,
+            format,
+            delay: Duration::from_millis(parsed(args, "--delay")?.unwrap_or(500)),
+            scale,
+            svg_dir: parsed(args, "--svg-dir")?,
+            json: args.iter().any(|(name, _)| *name == "--json"),
+        })
+    }
+}
+
+// コマンドライン引数を左から読み、`(オプション, 値)`の組に分ける。値を取らないオプションの値は空にする
+fn parse_args(args: &[String]) -> Result<Vec<(&str, &str)>, String> {
+    let mut rest = args.iter().skip(1).map(String::as_str);
+    let mut options = vec![];
+    while let Some(name) = rest.next() {
+        if FLAGS.contains(&name) {
+            options.push((name, ""));
+            continue;
+        }
+        if !OPTIONS.contains(&name) {
+            return Err(format!("unknown option: {}", name));
+        }
+        let value = rest
+            .next()
+            .ok_or_else(|| format!("{} needs a value", name))?;
+        options.push((name, value));
+    }
+    Ok(options)
+}
+// `name value`の形で指定されたオプションの値。最後に指定したものを使う
+fn parsed<T: FromStr>(args: &[(&str, &str)], name: &str) -> Result<Option<T>, String> {
+    args.iter()
+        .rev()
+        .find(|(option, _)| *option == name)
+        .map(|(_, value)| {
+            value
+                .parse()
+                .map_err(|_| format!("invalid value for {}: {}", name, value))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn args(s: &str) -> Vec<String> {
+        s.split(' ').map(|s| s.to_string()).collect()
+    }
+    #[test]
+    fn test_from_args() {
+        let options = Options::from_args(&args("record_player")).unwrap();
+        assert_eq!(options.output, PathBuf::from("TableturfBattle.gif"));
+        assert_eq!(options.format, Format::Gif);
+        assert_eq!(options.delay, Duration::from_millis(500));
+        assert!(!options.json);
+
+        let options = Options::from_args(&args(
+            "record_player --json --output tmp/a.png --delay 100 --svg-dir --json",
+        ))
+        .unwrap();
+        assert_eq!(options.format, Format::Apng);
+        assert_eq!(options.delay, Duration::from_millis(100));
+        assert_eq!(options.svg_dir, Some(PathBuf::from("--json")));
+        assert!(options.json);
+
+        let error = |s: &str| Options::from_args(&args(s)).unwrap_err();
+        assert_eq!(error("record_player -o a.gif"), "unknown option: -o");
+        assert_eq!(error("record_player --delay"), "--delay needs a value");
+        assert_eq!(
+            error("record_player --delay 0.5"),
+            "invalid value for --delay: 0.5"
+        );
+        assert_eq!(
+            error("record_player --scale 0"),
+            "--scale must be positive but was 0"
+        );
+        assert!(Options::from_args(&args("record_player --scale NaN")).is_err());
+        assert!(Options::from_args(&args("record_player --output a.webp")).is_err());
+        for option in OPTIONS {
+            assert!(USAGE.contains(&format!("  {} <", option)));
+        }
+        for flag in FLAGS {
+            assert!(USAGE.contains(&format!("  {} ", flag)));
+        }
+    }
+}
@@ -0,0 +1,719 @@
+//! botの思考部分。judgeはプロセス内のbotも外部プロセスのbotも`Agent`として同じように扱う。
+use crate::{
+    observation::Observation,
+    record::MoveLog,
+    seed::GameRng,
+    text_protocol::{format_card_ids, format_initial_input, format_turn_input},
+    Action, Card, CardId, Environment, FieldShape, GameError, MulliganAction, PlayerId,
+};
+use rand::Rng;
+use std::{
+    io::{Read, Write},
+    process::{Child, ChildStderr, ChildStdout, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// 対局の各場面で行動を決める。外部プロセスのbotは不正な出力をすることがあるので、すべて`Result`で返す。
+pub trait Agent {
+    fn name(&self) -> &str;
+    /// 対局の設定とカードカタログを見てデッキを選ぶ
+    fn choose_deck(
+        &mut self,
+        env: &Environment,
+        cards: &[Card],
+        field: &FieldShape,
+        player_id: PlayerId,
+    ) -> Result<Vec<CardId>, GameError>;
+    /// 配られた手札を見てマリガンするか決める
+    fn mulligan(&mut self, hands: &[CardId]) -> Result<MulliganAction, GameError>;
+    /// 合法手の中から行動を選ぶ
+    fn act(
+        &mut self,
+        observation: &Observation,
+        valid_actions: &[Action],
+    ) -> Result<Action, GameError>;
+    /// 直前の応答にかかった時間と、その間に書かれた標準エラー出力。記録しないbotは`None`を返す。
+    fn take_move_log(&mut self) -> Option<MoveLog> {
+        None
+    }
+    /// 対局が終わったときに呼ぶ。外部プロセスのbotは終了を待ち、終了状態を返す。
+    fn close(&mut self) -> Option<String> {
+        None
+    }
+}
+
+const STARTER_DECK: [CardId; 15] = [
+    6, 13, 22, 28, 40, 34, 45, 52, 55, 56, 159, 137, 141, 103, 92,
+];
+/// スターターデッキを使い、合法手からランダムに行動するbot
+pub struct RandomAgent {
+    name: String,
+    rng: GameRng,
+    deck: Vec<CardId>,
+}
+impl RandomAgent {
+    pub fn new(name: &str, rng: GameRng) -> Self {
+        Self {
+            name: name.to_string(),
+            rng,
+            deck: vec![],
+        }
+    }
+}
+impl Agent for RandomAgent {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn choose_deck(
+        &mut self,
+        env: &Environment,
+        cards: &[Card],
+        _field: &FieldShape,
+        _player_id: PlayerId,
+    ) -> Result<Vec<CardId>, GameError> {
+        // スターターデッキが使えなければカードカタログから選ぶ
+        self.deck = if STARTER_DECK.len() == env.deck_size
+            && STARTER_DECK
+                .iter()
+                .all(|card_id| cards.iter().any(|card| card.id == *card_id))
+        {
+            Vec::from(STARTER_DECK)
+        } else if env.is_deplicated_pick_enabled {
+            (0..env.deck_size)
+                .map(|_| cards[self.rng.gen_range(0..cards.len())].id)
+                .collect()
+        } else {
+            cards
+                .iter()
+                .take(env.deck_size)
+                .map(|card| card.id)
+                .collect()
+        };
+        Ok(self.deck.clone())
+    }
+    fn mulligan(&mut self, hands: &[CardId]) -> Result<MulliganAction, GameError> {
+        // デッキの最初のカードが手札に無ければ引き直す。デッキを選ぶ前に呼ばれたら引き直さない
+        if self
+            .deck
+            .first()
+            .is_some_and(|card_id| !hands.contains(card_id))
+        {
+            Ok(MulliganAction::Mulligan)
+        } else {
+            Ok(MulliganAction::Pass)
+        }
+    }
+    fn act(
+        &mut self,
+        _observation: &Observation,
+        valid_actions: &[Action],
+    ) -> Result<Action, GameError> {
+        Ok(valid_actions[self.rng.gen_range(0..valid_actions.len())])
+    }
+}
+
+/// 外部プロセスのbotの持ち時間。
+/// `clock`を指定すると、各ターンの持ち時間とは別に全ターンの合計時間にも上限を設ける（チェスクロック）。
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct TimeLimits {
+    /// 起動してbot名を出力するまでと、デッキを選ぶまでのそれぞれ
+    pub deck: Duration,
+    pub mulligan: Duration,
+    /// 1ターンあたり
+    pub turn: Duration,
+    pub clock: Option<Duration>,
+}
+impl Default for TimeLimits {
+    fn default() -> Self {
+        Self {
+            deck: Duration::from_secs(10),
+            mulligan: Duration::from_secs(10),
+            turn: Duration::from_secs(10),
+            clock: None,
+        }
+    }
+}
+
+// 標準出力が閉じられた直後はプロセスがまだ終了していないことがあるので、終了コードを待つ時間
+const EXIT_GRACE: Duration = Duration::from_millis(100);
+// 対局の後に標準入力を閉じてから、botが自分で終了するのを待つ時間
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+// 標準エラー出力のうち、1回の応答の分
+#[derive(Default)]
+struct StderrLog {
+    bytes: Vec<u8>,
+    dropped: usize,
+}
+impl StderrLog {
+    // 上限を超えた分は捨てて数だけ数える
+    fn push(&mut self, chunk: &[u8], limit: Option<usize>) {
+        let room = limit.map_or(chunk.len(), |limit| {
+            limit.saturating_sub(self.bytes.len()).min(chunk.len())
+        });
+        self.bytes.extend_from_slice(&chunk[..room]);
+        self.dropped += chunk.len() - room;
+    }
+    fn append(&mut self, other: StderrLog) {
+        self.bytes.extend(other.bytes);
+        self.dropped += other.dropped;
+    }
+}
+
+// 読むスレッドと共有する、まだどの応答にも割り当てていない標準エラー出力
+#[derive(Default)]
+struct StderrBuffer {
+    log: StderrLog,
+    limit: Option<usize>,
+    // 標準出力と標準エラー出力を最後まで読んだ
+    closed: bool,
+}
+type SharedStderr = Arc<(Mutex<StderrBuffer>, Condvar)>;
+
+/// テキストでやり取りする外部プロセスのbot。
+/// 読み書きは別スレッドで行い、持ち時間を過ぎたりプロセスが終了したりしたらbotを止めてエラーを返す。
+/// 一度エラーになったbotはそれ以降も同じエラーを返す。
+/// 標準エラー出力は応答ごとに区切って`take_move_log`で返す。応答の行を読んだ時点までに書かれた分がその応答の記録になる。
+pub struct ProcessAgent {
+    name: String,
+    process: Child,
+    stdin: Option<Sender<String>>,
+    stdout: Receiver<(String, StderrLog)>,
+    stderr: SharedStderr,
+    // 受け取った応答の分の標準エラー出力
+    log: StderrLog,
+    limits: TimeLimits,
+    // チェスクロックの残り時間
+    clock: Option<Duration>,
+    sent_at: Instant,
+    elapsed: Option<Duration>,
+    failure: Option<GameError>,
+}
+impl ProcessAgent {
+    /// 標準入出力をつないで起動し、最初の1行をbot名として読む。
+    /// bot名を読めなかった場合も起動には成功し、最初の`choose_deck`でエラーを返す。
+    pub fn spawn(mut command: Command, limits: TimeLimits) -> std::io::Result<Self> {
+        let mut process = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut stdin = process.stdin.take().expect("標準入力はpipeにしてある");
+        let (input, receiver) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            // 書き込めなくなったら終了し、エラーは読む側で終了として扱う
+            for text in receiver {
+                if stdin.write_all(text.as_bytes()).is_err() || stdin.flush().is_err() {
+                    break;
+                }
+            }
+        });
+        let stdout = process.stdout.take().expect("標準出力はpipeにしてある");
+        let stderr_pipe = process
+            .stderr
+            .take()
+            .expect("標準エラー出力はpipeにしてある");
+        let (sender, output) = mpsc::channel();
+        let stderr = SharedStderr::default();
+        let shared = Arc::clone(&stderr);
+        thread::spawn(move || {
+            read_output(stdout, stderr_pipe, sender, &shared);
+            let (buffer, closed) = &*shared;
+            buffer.lock().expect("他のスレッドはpanicしていない").closed = true;
+            closed.notify_all();
+        });
+        let mut agent = Self {
+            name: String::new(),
+            process,
+            stdin: Some(input),
+            stdout: output,
+            stderr,
+            log: StderrLog::default(),
+            limits,
+            clock: limits.clock,
+            sent_at: Instant::now(),
+            elapsed: None,
+            failure: None,
+        };
+        if let Ok(name) = agent.receive(limits.deck) {
+            agent.name = name.trim().to_string();
+        }
+        Ok(agent)
+    }
+    /// 1回の応答で記録する標準エラー出力の上限をバイト数で指定する。超えた分は捨てる。
+    pub fn with_stderr_limit(self, limit: usize) -> Self {
+        self.stderr
+            .0
+            .lock()
+            .expect("他のスレッドはpanicしていない")
+            .limit = Some(limit);
+        self
+    }
+    fn send(&mut self, text: &str) {
+        self.sent_at = Instant::now();
+        // 書き込み用のスレッドが終了していれば、botは終了しているので次の`receive`でエラーになる
+        if let Some(stdin) = &self.stdin {
+            stdin.send(text.to_string()).unwrap_or(());
+        }
+    }
+    fn receive(&mut self, limit: Duration) -> Result<String, GameError> {
+        if let Some(failure) = &self.failure {
+            self.elapsed = Some(Duration::ZERO);
+            return Err(failure.clone());
+        }
+        let received = self.stdout.recv_timeout(limit);
+        self.elapsed = Some(self.sent_at.elapsed());
+        let reason = match received {
+            Ok((s, log)) => {
+                self.log.append(log);
+                return Ok(s);
+            }
+            Err(RecvTimeoutError::Timeout) => GameError::Timeout(limit),
+            Err(RecvTimeoutError::Disconnected) => GameError::BotExited(self.exit_reason()),
+        };
+        self.process.kill().unwrap_or(());
+        self.process.wait().map(|_| ()).unwrap_or(());
+        self.drain_stderr();
+        self.failure = Some(reason.clone());
+        Err(reason)
+    }
+    // プロセスが終了した後に、最後の応答より後に書かれたエラー出力を読み終わるまで待って記録に加える。
+    // 子プロセスがpipeを開いたままのこともあるので、待つのは`EXIT_GRACE`まで
+    fn drain_stderr(&mut self) {
+        let (buffer, closed) = &*self.stderr;
+        let buffer = buffer.lock().expect("他のスレッドはpanicしていない");
+        let (mut buffer, _) = closed
+            .wait_timeout_while(buffer, EXIT_GRACE, |buffer| !buffer.closed)
+            .expect("他のスレッドはpanicしていない");
+        self.log.append(std::mem::take(&mut buffer.log));
+    }
+    fn exit_reason(&mut self) -> String {
+        let start = Instant::now();
+        loop {
+            match self.process.try_wait() {
+                Ok(Some(status)) => return status.to_string(),
+                Ok(None) if start.elapsed() < EXIT_GRACE => thread::sleep(EXIT_GRACE / 10),
+                _ => return "closed stdout".to_string(),
+            }
+        }
+    }
+}
+
+// `close`を呼ばずに捨てられても、botのプロセスを残さない
+impl Drop for ProcessAgent {
+    fn drop(&mut self) {
+        if let Ok(None) = self.process.try_wait() {
+            self.process.kill().unwrap_or(());
+            self.process.wait().map(|_| ()).unwrap_or(());
+        }
+    }
+}
+
+// 標準出力を1行ずつ、その行を読むまでに書かれた標準エラー出力と一緒に送る。
+// 応答より前に書かれたエラー出力は、応答を読んだ時点で読み終わっているかpipeに残っているので、
+// 標準出力を読むたびにpipeに残っている分を読んでから行を送る。
+#[cfg(unix)]
+fn read_output(
+    mut stdout: ChildStdout,
+    mut stderr: ChildStderr,
+    sender: Sender<(String, StderrLog)>,
+    shared: &SharedStderr,
+) {
+    use std::os::unix::io::AsRawFd;
+    let mut chunk = [0; 4096];
+    let mut read_stderr = |stderr: &mut ChildStderr, len: usize| {
+        let n = stderr.read(&mut chunk[..len]).unwrap_or(0);
+        let mut buffer = shared.0.lock().expect("他のスレッドはpanicしていない");
+        let limit = buffer.limit;
+        buffer.log.push(&chunk[..n], limit);
+        n
+    };
+    let mut line = vec![];
+    let mut sender = Some(sender);
+    let mut stderr_open = true;
+    while sender.is_some() || stderr_open {
+        let mut fds = [
+            libc::pollfd {
+                fd: if sender.is_some() {
+                    stdout.as_raw_fd()
+                } else {
+                    -1
+                },
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: if stderr_open { stderr.as_raw_fd() } else { -1 },
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        // SAFETY: 2つの`pollfd`を指す有効なポインタを渡している
+        if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } < 0 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            break;
+        }
+        if fds[1].revents != 0 {
+            stderr_open = read_stderr(&mut stderr, 4096) > 0;
+        }
+        if fds[0].revents == 0 {
+            continue;
+        }
+        let mut buf = [0; 4096];
+        let n = stdout.read(&mut buf).unwrap_or(0);
+        line.extend_from_slice(&buf[..n]);
+        let mut lines = vec![];
+        while let Some(end) = line.iter().position(|&b| b == b'\n') {
+            lines.push(line.drain(..=end).collect::<Vec<u8>>());
+        }
+        if n == 0 && !line.is_empty() {
+            // 改行で終わらない最後の行
+            lines.push(std::mem::take(&mut line));
+        }
+        if !lines.is_empty() && stderr_open {
+            let mut pending: libc::c_int = 0;
+            // SAFETY: FIONREADは`pending`に読まれていないバイト数を書き込むだけ
+            if unsafe { libc::ioctl(stderr.as_raw_fd(), libc::FIONREAD, &mut pending) } == 0 {
+                let mut rest = pending.max(0) as usize;
+                while rest > 0 {
+                    let read = read_stderr(&mut stderr, rest.min(4096));
+                    if read == 0 {
+                        stderr_open = false;
+                        break;
+                    }
+                    rest -= read;
+                }
+            }
+        }
+        for line in lines {
+            let log =
+                std::mem::take(&mut shared.0.lock().expect("他のスレッドはpanicしていない").log);
+            let line = String::from_utf8_lossy(&line).to_string();
+            if let Some(sender) = &sender {
+                if sender.send((line, log)).is_err() {
+                    return;
+                }
+            }
+        }
+        if n == 0 {
+            sender = None;
+        }
+    }
+}
+// pipeの残りを調べられないので、標準エラー出力は別のスレッドで読み、行を読んだ時点までに読めた分を送る
+#[cfg(not(unix))]
+fn read_output(
+    stdout: ChildStdout,
+    mut stderr: ChildStderr,
+    sender: Sender<(String, StderrLog)>,
+    shared: &SharedStderr,
+) {
+    use std::io::{BufRead, BufReader};
+    let reader = {
+        let shared = Arc::clone(shared);
+        thread::spawn(move || {
+            let mut chunk = [0; 4096];
+            while let Ok(n @ 1..) = stderr.read(&mut chunk) {
+                let mut buffer = shared.0.lock().expect("他のスレッドはpanicしていない");
+                let limit = buffer.limit;
+                buffer.log.push(&chunk[..n], limit);
+            }
+        })
+    };
+    let mut stdout = BufReader::new(stdout);
+    loop {
+        let mut s = String::new();
+        if !matches!(stdout.read_line(&mut s), Ok(1..)) {
+            break;
+        }
+        let log = std::mem::take(&mut shared.0.lock().expect("他のスレッドはpanicしていない").log);
+        if sender.send((s, log)).is_err() {
+            break;
+        }
+    }
+    drop(sender);
+    reader.join().unwrap_or(());
+}
+impl Agent for ProcessAgent {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn choose_deck(
+        &mut self,
+        env: &Environment,
+        cards: &[Card],
+        field: &FieldShape,
+        player_id: PlayerId,
+    ) -> Result<Vec<CardId>, GameError> {
+        self.send(&format_initial_input(env, cards, field, Some(player_id)));
+        let s = self.receive(self.limits.deck)?;
+        s.split_whitespace()
+            .map(|s| s.parse::<CardId>())
+            .collect::<Result<Vec<CardId>, _>>()
+            .map_err(|_| GameError::MalformedText(s.clone()))
+    }
+    fn mulligan(&mut self, hands: &[CardId]) -> Result<MulliganAction, GameError> {
+        self.send(&format!("{}\n", format_card_ids(hands)));
+        self.receive(self.limits.mulligan)?.parse()
+    }
+    fn act(
+        &mut self,
+        observation: &Observation,
+        valid_actions: &[Action],
+    ) -> Result<Action, GameError> {
+        let limit = match self.clock {
+            Some(clock) => self.limits.turn.min(clock),
+            None => self.limits.turn,
+        };
+        self.send(&format_turn_input(observation, valid_actions));
+        let s = self.receive(limit)?;
+        if let (Some(clock), Some(elapsed)) = (&mut self.clock, self.elapsed) {
+            *clock = clock.saturating_sub(elapsed);
+        }
+        s.parse()
+    }
+    fn take_move_log(&mut self) -> Option<MoveLog> {
+        let mut log = std::mem::take(&mut self.log);
+        // 上限を指定する前に読んだ分や、複数回に分けて読んだ分もここで切り詰める
+        let limit = self
+            .stderr
+            .0
+            .lock()
+            .expect("他のスレッドはpanicしていない")
+            .limit;
+        if let Some(limit) = limit.filter(|&limit| log.bytes.len() > limit) {
+            log.dropped += log.bytes.len() - limit;
+            log.bytes.truncate(limit);
+        }
+        Some(MoveLog {
+            elapsed: self.elapsed.take().unwrap_or_default(),
+            stderr: String::from_utf8_lossy(&log.bytes).to_string(),
+            stderr_dropped: log.dropped,
+        })
+    }
+    fn close(&mut self) -> Option<String> {
+        // 標準入力を閉じると、書き込み用のスレッドが終了してbotに入力の終わりが伝わる
+        self.stdin = None;
+        let start = Instant::now();
+        let status = loop {
+            match self.process.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) if start.elapsed() < CLOSE_GRACE => thread::sleep(CLOSE_GRACE / 100),
+                Ok(None) => {
+                    self.process.kill().unwrap_or(());
+                    break self.process.wait();
+                }
+                Err(why) => break Err(why),
+            }
+        };
+        self.drain_stderr();
+        Some(match status {
+            Ok(status) => status.to_string(),
+            Err(why) => why.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        catalog::parse_card_catalog, seed::game_rng, setup::GameSetup,
+        text_protocol::run_text_protocol, Field, State,
+    };
+    use std::{collections::HashMap, io::Cursor};
+
+    #[test]
+    fn test_random_agent_mulligan() {
+        // デッキを選ぶ前でもマリガンできる
+        let mut agent = RandomAgent::new("a", game_rng(0));
+        assert_eq!(agent.mulligan(&[1, 2]), Ok(MulliganAction::Pass));
+        let env = Environment::new(2, 6, 4, 3, false);
+        let catalog =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        let deck = agent
+            .choose_deck(&env, &catalog, &Field::default().shape, 0)
+            .unwrap();
+        assert_eq!(agent.mulligan(&deck[..1]), Ok(MulliganAction::Pass));
+        assert_eq!(agent.mulligan(&[]), Ok(MulliganAction::Mulligan));
+    }
+    // judgeの代わりに`RandomAgent`同士で対局し、片方に送るはずのテキストを作る
+    #[test]
+    fn test_run_text_protocol() {
+        let env = Environment::new(2, 6, 4, 3, false);
+        let catalog =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        let cards = catalog
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<_, _>>();
+        let field = Field::default();
+        let mut agents = (0..2)
+            .map(|player_id| RandomAgent::new("random", game_rng(player_id)))
+            .collect::<Vec<RandomAgent>>();
+
+        let mut input = format_initial_input(&env, &catalog, &field.shape, Some(1));
+        let mut setup = GameSetup::new(&env);
+        let mut rng = game_rng(2);
+        for (player_id, agent) in agents.iter_mut().enumerate() {
+            let deck = agent
+                .choose_deck(&env, &catalog, &field.shape, player_id)
+                .unwrap();
+            setup.pick_deck(&env, &cards, player_id, &deck).unwrap();
+            setup.shuffle(player_id, &mut rng);
+            let action = agent.mulligan(setup.hands(player_id)).unwrap();
+            setup.mulligan(player_id, action);
+            if player_id == 1 {
+                input += &format!("{}\n", format_card_ids(setup.hands(player_id)));
+            }
+            if action == MulliganAction::Mulligan {
+                setup.shuffle(player_id, &mut rng);
+            }
+        }
+        let mut state: State = setup.into_state(&env, &cards, &field).unwrap();
+        let mut expected = vec![];
+        while !state.is_done(&env) {
+            let mut actions = vec![];
+            for (player_id, agent) in agents.iter_mut().enumerate() {
+                let valid_actions = state.generate_valid_actions(&cards, player_id);
+                let observation = state.observe(player_id);
+                if player_id == 1 {
+                    input += &format_turn_input(&observation, &valid_actions);
+                }
+                actions.push(agent.act(&observation, &valid_actions).unwrap());
+            }
+            expected.push(actions[1]);
+            state.apply(&env, &cards, &actions).unwrap();
+        }
+
+        // 同じ種のbotをテキスト越しに動かすと同じ行動をする
+        let mut output = vec![];
+        let mut agent = RandomAgent::new("random", game_rng(1));
+        run_text_protocol(&mut agent, &mut Cursor::new(input), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "random");
+        assert_eq!(lines[1], format_card_ids(&agents[1].deck));
+        assert_eq!(
+            lines[3..]
+                .iter()
+                .map(|line| line.parse::<Action>().unwrap())
+                .collect::<Vec<Action>>(),
+            expected
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_process_agent_failure() {
+        let env = Environment::new(2, 6, 4, 3, false);
+        let catalog =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        let field = Field::default();
+        let limits = TimeLimits {
+            deck: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let spawn = |script: &str| {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(script);
+            ProcessAgent::spawn(command, limits).unwrap()
+        };
+
+        // 応答しないbotは持ち時間で打ち切り、以降も同じエラーを返す
+        let mut agent = spawn("echo hang; sleep 10");
+        assert_eq!(agent.name(), "hang");
+        let expected = Err(GameError::Timeout(limits.deck));
+        assert_eq!(agent.choose_deck(&env, &catalog, &field.shape, 0), expected);
+        assert_eq!(agent.mulligan(&[]), Err(GameError::Timeout(limits.deck)));
+
+        // 終了したbotは終了コードを理由にする
+        let mut agent = spawn("echo quit; read line; exit 3");
+        assert_eq!(
+            agent.choose_deck(&env, &catalog, &field.shape, 0),
+            Err(GameError::BotExited("exit status: 3".to_string()))
+        );
+
+        // bot名を出力しないまま終了した
+        let mut agent = spawn("exit 0");
+        assert_eq!(agent.name(), "");
+        assert!(agent
+            .choose_deck(&env, &catalog, &field.shape, 0)
+            .unwrap_err()
+            .is_bot_failure());
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_process_agent_log() {
+        let env = Environment::new(2, 6, 4, 3, false);
+        let catalog =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg("echo log; echo starting >&2; read line; echo abcdefgh >&2; echo 1 2 3; exit 5");
+        let mut agent = ProcessAgent::spawn(command, TimeLimits::default())
+            .unwrap()
+            .with_stderr_limit(4);
+        assert_eq!(
+            agent.choose_deck(&env, &catalog, &Field::default().shape, 0),
+            Ok(vec![1, 2, 3])
+        );
+        assert_eq!(agent.close(), Some("exit status: 5".to_string()));
+        let log = agent.take_move_log().unwrap();
+        assert_eq!(log.stderr, "star");
+        assert_eq!(log.stderr_dropped, "starting\nabcdefgh\n".len() - 4);
+        assert_eq!(agent.take_move_log().unwrap(), MoveLog::default());
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_process_agent_log_per_move() {
+        let env = Environment::new(2, 6, 4, 3, false);
+        let catalog =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        // 応答の直前に多めに書いたエラー出力も、その応答の記録になる。
+        // マリガンの分はマリガンの入力を読んでから書くので、デッキの記録には入らない
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(
+            "echo log; read line; seq 100000 >&2; echo deck >&2; echo 1 2 3; \
+             while read line; do [ \"$line\" = 999 ] && break; done; \
+             echo mulligan >&2; echo PASS; read line; echo bye >&2",
+        );
+        let mut agent = ProcessAgent::spawn(command, TimeLimits::default()).unwrap();
+        assert_eq!(agent.take_move_log().unwrap().stderr, "");
+        assert_eq!(
+            agent.choose_deck(&env, &catalog, &Field::default().shape, 0),
+            Ok(vec![1, 2, 3])
+        );
+        let expected = (1..=100000).map(|i| format!("{}\n", i)).collect::<String>() + "deck\n";
+        assert_eq!(agent.take_move_log().unwrap().stderr, expected);
+        assert_eq!(agent.mulligan(&[999]), Ok(MulliganAction::Pass));
+        assert_eq!(agent.take_move_log().unwrap().stderr, "mulligan\n");
+        // 最後の応答より後の出力は、終了を待ってから記録に加える
+        assert_eq!(agent.close(), Some("exit status: 0".to_string()));
+        assert_eq!(agent.take_move_log().unwrap().stderr, "bye\n");
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_process_agent_drop() {
+        // `close`を呼ばずに捨てたbotのプロセスは止める
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo sleepy; exec sleep 10");
+        let agent = ProcessAgent::spawn(command, TimeLimits::default()).unwrap();
+        let pid = agent.process.id().to_string();
+        drop(agent);
+        let alive = Command::new("kill")
+            .args(["-0", &pid])
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!alive.success());
+    }
+}
@@ -0,0 +1,263 @@
+//! フィールドをビット列で表現し、カードを置けるかをシフトとマスクで判定する。
+//! テキスト形式の`FieldShape`との相互変換ができ、合法手の列挙に使う。
+use crate::{CardShape, CardSquareType, FieldShape, FieldSquareType, PlayerId};
+
+/// ビットボードで扱えるフィールドの最大の高さ・幅
+pub const MAX_FIELD_SIZE: usize = 32;
+/// 1行を1つの整数で表す。x列目が下位からxビット目に対応する。
+pub type Rows = [u64; MAX_FIELD_SIZE];
+
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub struct Bitboard {
+    pub height: usize,
+    pub width: usize,
+    /// プレイヤーごとの通常マス
+    pub colored: Vec<Rows>,
+    /// プレイヤーごとのスペシャルマス
+    pub special: Vec<Rows>,
+    /// 活性化済みのスペシャルマス
+    pub activated: Rows,
+    pub block: Rows,
+    pub empty: Rows,
+}
+impl Bitboard {
+    /// フィールドが大きすぎる場合はNoneを返す。
+    pub fn new(field: &FieldShape, player_size: usize) -> Option<Self> {
+        if MAX_FIELD_SIZE < field.height || MAX_FIELD_SIZE < field.width {
+            return None;
+        }
+        let mut board = Self {
+            height: field.height,
+            width: field.width,
+            colored: vec![[0; MAX_FIELD_SIZE]; player_size],
+            special: vec![[0; MAX_FIELD_SIZE]; player_size],
+            activated: [0; MAX_FIELD_SIZE],
+            block: [0; MAX_FIELD_SIZE],
+            empty: [0; MAX_FIELD_SIZE],
+        };
+        for (y, row) in field.squares.iter().enumerate() {
+            for (x, square) in row.iter().enumerate() {
+                let bit = 1 << x;
+                match *square {
+                    FieldSquareType::Colored { player_id } => board.colored[player_id][y] |= bit,
+                    FieldSquareType::Special {
+                        player_id,
+                        activeted,
+                    } => {
+                        board.special[player_id][y] |= bit;
+                        if activeted {
+                            board.activated[y] |= bit;
+                        }
+                    }
+                    FieldSquareType::Block => board.block[y] |= bit,
+                    FieldSquareType::Empty => board.empty[y] |= bit,
+                }
+            }
+        }
+        Some(board)
+    }
+    pub fn to_field_shape(&self) -> FieldShape {
+        let mut squares = vec![vec![FieldSquareType::Empty; self.width]; self.height];
+        for (y, row) in squares.iter_mut().enumerate() {
+            for (x, square) in row.iter_mut().enumerate() {
+                let bit = 1 << x;
+                if self.block[y] & bit != 0 {
+                    *square = FieldSquareType::Block;
+                }
+                for player_id in 0..self.colored.len() {
+                    if self.colored[player_id][y] & bit != 0 {
+                        *square = FieldSquareType::Colored { player_id };
+                    }
+                    if self.special[player_id][y] & bit != 0 {
+                        *square = FieldSquareType::Special {
+                            player_id,
+                            activeted: self.activated[y] & bit != 0,
+                        };
+                    }
+                }
+            }
+        }
+        FieldShape {
+            height: self.height,
+            width: self.width,
+            squares,
+        }
+    }
+    /// 左上が(oy, ox)になるようにカードを置けるか。
+    /// 通常のPUTは空白マスだけに重なり、自分のマスに接している必要がある。
+    pub fn can_put(&self, mask: &CardMask, player_id: PlayerId, oy: usize, ox: usize) -> bool {
+        if !self.is_inside(mask, oy, ox) {
+            return false;
+        }
+        for i in 0..mask.height {
+            if (mask.squares[i] << ox) & !self.empty[oy + i] != 0 {
+                return false;
+            }
+        }
+        self.is_adjacent(mask, oy, ox, |y| {
+            self.colored[player_id][y] | self.special[player_id][y]
+        })
+    }
+    /// 左上が(oy, ox)になるようにスペシャルアタックでカードを置けるか。
+    /// スペシャルマスとブロックマス以外に重なり、自分のスペシャルマスに接している必要がある。
+    pub fn can_special_put(
+        &self,
+        mask: &CardMask,
+        player_id: PlayerId,
+        oy: usize,
+        ox: usize,
+    ) -> bool {
+        if !self.is_inside(mask, oy, ox) {
+            return false;
+        }
+        for i in 0..mask.height {
+            let forbidden = self
+                .special
+                .iter()
+                .fold(self.block[oy + i], |acc, s| acc | s[oy + i]);
+            if (mask.squares[i] << ox) & forbidden != 0 {
+                return false;
+            }
+        }
+        self.is_adjacent(mask, oy, ox, |y| self.special[player_id][y])
+    }
+    fn is_inside(&self, mask: &CardMask, oy: usize, ox: usize) -> bool {
+        oy + mask.height <= self.height && ox + mask.width <= self.width
+    }
+    fn is_adjacent(
+        &self,
+        mask: &CardMask,
+        oy: usize,
+        ox: usize,
+        own_squares: impl Fn(usize) -> u64,
+    ) -> bool {
+        // neighborsは上下左右に1マス広げてあるので1行1列ずれる
+        for (i, &neighbors) in mask.neighbors.iter().enumerate() {
+            let y = (oy + i).wrapping_sub(1);
+            if self.height <= y {
+                continue;
+            }
+            let shifted = if ox == 0 {
+                neighbors >> 1
+            } else {
+                neighbors << (ox - 1)
+            };
+            if shifted & own_squares(y) != 0 {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// 回転済みのカードの形をビット列にしたもの
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub struct CardMask {
+    pub height: usize,
+    pub width: usize,
+    /// 塗るマス（通常マスとスペシャルマス）
+    pub squares: Vec<u64>,
+    /// 塗るマスとその8近傍。上下左右に1マスずつ広げた(height+2)x(width+2)の範囲を持つ。
+    pub neighbors: Vec<u64>,
+}
+impl CardMask {
+    pub fn new(shape: &CardShape) -> Self {
+        let mut squares = vec![0; shape.height];
+        let mut neighbors = vec![0; shape.height + 2];
+        for (i, row) in shape.squares.iter().enumerate() {
+            for (j, square) in row.iter().enumerate() {
+                if *square == CardSquareType::Empty {
+                    continue;
+                }
+                squares[i] |= 1 << j;
+                for neighbor in neighbors.iter_mut().skip(i).take(3) {
+                    *neighbor |= 0b111 << j;
+                }
+            }
+        }
+        Self {
+            height: shape.height,
+            width: shape.width,
+            squares,
+            neighbors,
+        }
+    }
+    /// いずれかのマスが`anchors`に乗るような、カードの左上の座標の集合
+    pub fn positions_touching(&self, anchors: &Rows) -> Rows {
+        let mut positions = [0; MAX_FIELD_SIZE];
+        for (y, &anchor_row) in anchors.iter().enumerate() {
+            if anchor_row == 0 {
+                continue;
+            }
+            for (i, &row) in self.squares.iter().enumerate().take(y + 1) {
+                let mut bits = row;
+                while bits != 0 {
+                    let j = bits.trailing_zeros();
+                    bits &= bits - 1;
+                    positions[y - i] |= anchor_row >> j;
+                }
+            }
+        }
+        positions
+    }
+}
+
+/// 座標の集合をビット列にする
+pub fn to_rows<'a>(squares: impl IntoIterator<Item = &'a (usize, usize)>) -> Rows {
+    let mut rows = [0; MAX_FIELD_SIZE];
+    for &(y, x) in squares {
+        rows[y] |= 1 << x;
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_field_shape_round_trip() {
+        let field = FieldShape::new("Y.#\nby.\n#.B").unwrap();
+        let mut board = Bitboard::new(&field, 2).unwrap();
+        assert_eq!(board.to_field_shape(), field);
+        board.activated[0] |= 1;
+        assert_eq!(
+            board.to_field_shape().squares[0][0],
+            FieldSquareType::Special {
+                player_id: 0,
+                activeted: true
+            }
+        );
+    }
+    #[test]
+    fn test_card_mask() {
+        let mask = CardMask::new(&CardShape::new("y.\n.Y").unwrap());
+        assert_eq!(mask.squares, vec![0b01, 0b10]);
+        assert_eq!(mask.neighbors, vec![0b0111, 0b1111, 0b1111, 0b1110]);
+    }
+    #[test]
+    fn test_positions_touching() {
+        let mask = CardMask::new(&CardShape::new("y.\n.Y").unwrap());
+        let positions = mask.positions_touching(&to_rows(&[(2, 3)]));
+        assert_eq!(positions, to_rows(&[(2, 3), (1, 2)]));
+    }
+    #[test]
+    fn test_can_put() {
+        let field = FieldShape::new("....\n.Y..\n...b\n#...").unwrap();
+        let board = Bitboard::new(&field, 2).unwrap();
+        let mask = CardMask::new(&CardShape::new("yy").unwrap());
+        assert!(board.can_put(&mask, 0, 0, 0));
+        assert!(board.can_put(&mask, 0, 2, 1));
+        // 空白マス以外に重なる
+        assert!(!board.can_put(&mask, 0, 1, 0));
+        // 接していない
+        assert!(!board.can_put(&mask, 0, 3, 2));
+        // はみ出す
+        assert!(!board.can_put(&mask, 0, 0, 3));
+        assert!(board.can_put(&mask, 1, 3, 2));
+        // スペシャルアタックは相手のマスに重ねられるがスペシャルマスとブロックマスには重ねられない
+        assert!(board.can_special_put(&mask, 0, 2, 2));
+        assert!(!board.can_special_put(&mask, 0, 1, 1));
+        assert!(!board.can_special_put(&mask, 0, 3, 0));
+        assert!(!board.can_special_put(&mask, 1, 3, 2));
+    }
+}
@@ -0,0 +1,330 @@
+//! JSONで書かれたカタログの読み込みと検証。
+use crate::{
+    Card, CardId, CardShape, CardSquareType, Field, FieldId, FieldShape, FieldSquareType, PlayerId,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt::{Display, Formatter},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+/// カードの形の最大の高さ・幅
+pub const MAX_CARD_SIZE: usize = 8;
+/// スペシャルアタックに必要なスペシャルポイントの範囲
+pub const CARD_COSTS: RangeInclusive<usize> = 1..=6;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum CatalogError {
+    /// ファイルが読めない
+    Io { path: String, reason: String },
+    /// JSONとして解釈できない
+    Json(String),
+    /// 同じIDが複数ある
+    DuplicateId(usize),
+    /// カードの形やコストが不正
+    InvalidCard { id: CardId, reason: String },
+    /// フィールドの形が不正
+    InvalidField { id: FieldId, reason: String },
+}
+impl Display for CatalogError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            CatalogError::Io { path, reason } => write!(f, "couldn't read {}: {}", path, reason),
+            CatalogError::Json(reason) => write!(f, "invalid JSON: {}", reason),
+            CatalogError::DuplicateId(id) => write!(f, "id {} is used more than once", id),
+            CatalogError::InvalidCard { id, reason } => write!(f, "card {}: {}", id, reason),
+            CatalogError::InvalidField { id, reason } => {
+                write!(f, "field {}: {}", id, reason)
+            }
+        }
+    }
+}
+impl std::error::Error for CatalogError {}
+
+fn read_to_string(path: &Path) -> Result<String, CatalogError> {
+    std::fs::read_to_string(path).map_err(|why| CatalogError::Io {
+        path: path.display().to_string(),
+        reason: why.to_string(),
+    })
+}
+
+/// カードカタログの1要素。棋譜にも同じ形で書く。
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct CardJson {
+    pub id: CardId,
+    pub name: String,
+    pub cost: usize,
+    pub squares: String,
+}
+impl From<&Card> for CardJson {
+    fn from(card: &Card) -> Self {
+        Self {
+            id: card.id,
+            name: card.name.clone(),
+            cost: card.cost,
+            squares: card.shape.to_string(),
+        }
+    }
+}
+impl CardJson {
+    pub fn to_card(&self) -> Result<Card, CatalogError> {
+        new_card(self.id, &self.name, self.cost, &self.squares)
+    }
+    /// 棋譜に記録されたカードを作る。記録した時点のカードで再生できるよう、形として読めることだけを確かめる。
+    pub fn to_recorded_card(&self) -> Result<Card, CatalogError> {
+        let invalid = |reason: String| CatalogError::InvalidCard {
+            id: self.id,
+            reason,
+        };
+        let seed = CardShape::new(&self.squares).map_err(|why| invalid(why.to_string()))?;
+        if seed
+            .squares
+            .iter()
+            .flatten()
+            .all(|&square| square == CardSquareType::Empty)
+        {
+            return Err(invalid("shape is empty".to_string()));
+        }
+        Ok(Card::new(
+            self.id,
+            &self.name,
+            self.cost,
+            CardShape::trim(&seed),
+        ))
+    }
+}
+
+/// 検証してからカードを作る。JSONのカタログもjudgeから送られたカードもここを通す。
+/// `squares`は周りに空白マスがあってもよく、取り除いた形をカードの形にする。
+pub fn new_card(id: CardId, name: &str, cost: usize, squares: &str) -> Result<Card, CatalogError> {
+    let invalid = |reason: String| CatalogError::InvalidCard { id, reason };
+    let seed = CardShape::new(squares).map_err(|why| invalid(why.to_string()))?;
+    if seed
+        .squares
+        .iter()
+        .flatten()
+        .all(|&square| square == CardSquareType::Empty)
+    {
+        return Err(invalid("shape is empty".to_string()));
+    }
+    // 大きさは周りの空白マスを取り除いてから確かめる
+    let shape = CardShape::trim(&seed);
+    if MAX_CARD_SIZE < shape.height || MAX_CARD_SIZE < shape.width {
+        return Err(invalid(format!(
+            "shape is {}x{} but must fit in {}x{}",
+            shape.height, shape.width, MAX_CARD_SIZE, MAX_CARD_SIZE
+        )));
+    }
+    let n_specials = shape
+        .squares
+        .iter()
+        .flatten()
+        .filter(|&&square| square == CardSquareType::Special)
+        .count();
+    if n_specials != 1 {
+        return Err(invalid(format!(
+            "must have exactly 1 special square but has {}",
+            n_specials
+        )));
+    }
+    if !CARD_COSTS.contains(&cost) {
+        return Err(invalid(format!(
+            "cost must be in {}..={} but was {}",
+            CARD_COSTS.start(),
+            CARD_COSTS.end(),
+            cost
+        )));
+    }
+    Ok(Card::new(id, name, cost, shape))
+}
+pub fn parse_card_catalog(text: &str) -> Result<Vec<Card>, CatalogError> {
+    let raw_cards = serde_json::from_str::<Vec<CardJson>>(text)
+        .map_err(|why| CatalogError::Json(why.to_string()))?;
+    let mut ids = HashSet::new();
+    let mut cards = vec![];
+    for raw in raw_cards {
+        if !ids.insert(raw.id) {
+            return Err(CatalogError::DuplicateId(raw.id));
+        }
+        cards.push(raw.to_card()?);
+    }
+    Ok(cards)
+}
+pub fn load_card_catalog(path: impl AsRef<Path>) -> Result<Vec<Card>, CatalogError> {
+    parse_card_catalog(&read_to_string(path.as_ref())?)
+}
+
+/// フィールドカタログの1要素。棋譜にも同じ形で書く。
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct FieldJson {
+    pub id: FieldId,
+    pub name: String,
+    pub squares: String,
+}
+impl From<&Field> for FieldJson {
+    fn from(field: &Field) -> Self {
+        Self {
+            id: field.id,
+            name: field.name.clone(),
+            squares: field.shape.to_string(),
+        }
+    }
+}
+
+/// スペシャルマスを持つプレイヤー
+pub fn start_players(shape: &FieldShape) -> BTreeSet<PlayerId> {
+    shape
+        .squares
+        .iter()
+        .flatten()
+        .filter_map(|square| match square {
+            FieldSquareType::Special { player_id, .. } => Some(*player_id),
+            _ => None,
+        })
+        .collect()
+}
+
+/// 各フィールドは2人以上のプレイヤーのスペシャルマスを持ち、
+/// プレイヤー0から順にスペシャルマスが無いプレイヤーを飛ばしていないことを確認する。
+pub fn parse_field_catalog(text: &str) -> Result<Vec<Field>, CatalogError> {
+    let raw_fields = serde_json::from_str::<Vec<FieldJson>>(text)
+        .map_err(|why| CatalogError::Json(why.to_string()))?;
+    let mut ids = HashSet::new();
+    let mut fields = vec![];
+    for raw in raw_fields {
+        if !ids.insert(raw.id) {
+            return Err(CatalogError::DuplicateId(raw.id));
+        }
+        let invalid = |reason: String| CatalogError::InvalidField { id: raw.id, reason };
+        let shape = FieldShape::new(&raw.squares).map_err(|why| invalid(why.to_string()))?;
+        let players = start_players(&shape);
+        if players.len() < 2 {
+            return Err(invalid(format!(
+                "needs start special squares for at least 2 players but has {}",
+                players.len()
+            )));
+        }
+        if let Some(player_id) = (0..players.len()).find(|p| !players.contains(p)) {
+            return Err(invalid(format!(
+                "player {} has no start special square",
+                player_id
+            )));
+        }
+        fields.push(Field {
+            id: raw.id,
+            name: raw.name,
+            shape,
+        });
+    }
+    Ok(fields)
+}
+pub fn load_field_catalog(path: impl AsRef<Path>) -> Result<Vec<Field>, CatalogError> {
+    parse_field_catalog(&read_to_string(path.as_ref())?)
+}
+/// IDまたは名前でフィールドを探す
+pub fn find_field<'a>(fields: &'a [Field], key: &str) -> Option<&'a Field> {
+    fields
+        .iter()
+        .find(|field| key.parse::<FieldId>() == Ok(field.id) || field.name == key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_card_catalog() {
+        let cards =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        assert_eq!(cards.len(), 9);
+        // 周りの空白マスは取り除かれる
+        let card = new_card(1, "hoge", 2, "....\n.yY.\n....").unwrap();
+        assert_eq!(card.shape, CardShape::new("yY").unwrap());
+        assert_eq!(card.power, 2);
+        // 取り除いた後の形が8x8に収まれば、周りの空白マスで8x8を超えていてもよい
+        let mut rows = vec![".".repeat(MAX_CARD_SIZE + 1); MAX_CARD_SIZE + 1];
+        rows[4] = format!("....yY{}", ".".repeat(MAX_CARD_SIZE - 5));
+        let card = new_card(1, "hoge", 2, &rows.join("\n")).unwrap();
+        assert_eq!(card.shape, CardShape::new("yY").unwrap());
+    }
+    #[test]
+    fn test_invalid_card_catalog() {
+        let invalid = |squares: &str, cost: usize| {
+            matches!(
+                new_card(1, "hoge", cost, squares),
+                Err(CatalogError::InvalidCard { id: 1, .. })
+            )
+        };
+        assert!(!invalid("yY", 1));
+        assert!(invalid("....\n....", 1));
+        assert!(invalid("yx", 1));
+        assert!(invalid("yY\ny", 1));
+        assert!(invalid("yy", 1));
+        assert!(invalid("YY", 1));
+        assert!(invalid(&format!("y{}Y", ".".repeat(MAX_CARD_SIZE - 1)), 1));
+        assert!(invalid("yY", 0));
+        assert!(invalid("yY", CARD_COSTS.end() + 1));
+        assert_eq!(
+            parse_card_catalog(
+                r#"[{"id": 1, "name": "a", "cost": 1, "squares": "Y"}, {"id": 1, "name": "b", "cost": 1, "squares": "Y"}]"#
+            )
+            .map(|cards| cards.len()),
+            Err(CatalogError::DuplicateId(1))
+        );
+    }
+    #[test]
+    fn test_field_catalog() {
+        let fields = parse_field_catalog(include_str!("../resources/field_catalog.json")).unwrap();
+        assert_eq!(fields[0], Field::default());
+        assert_eq!(find_field(&fields, "1"), Some(&fields[0]));
+        assert_eq!(find_field(&fields, &fields[1].name), Some(&fields[1]));
+        assert_eq!(find_field(&fields, "hoge"), None);
+        for field in fields[..4].iter() {
+            assert_eq!(start_players(&field.shape), BTreeSet::from([0, 1]));
+        }
+        // 3人用と4人用のステージ
+        assert_eq!(start_players(&fields[4].shape), BTreeSet::from([0, 1, 2]));
+        assert_eq!(
+            start_players(&fields[5].shape),
+            BTreeSet::from([0, 1, 2, 3])
+        );
+    }
+    #[test]
+    fn test_invalid_field_catalog() {
+        let parse = |squares: &str| {
+            parse_field_catalog(&format!(
+                r#"[{{"id": 1, "name": "hoge", "squares": "{}"}}]"#,
+                squares
+            ))
+        };
+        assert!(parse("Y.\\n.B").is_ok());
+        assert!(parse("YG\\n.B").is_ok());
+        assert!(matches!(
+            parse("Y.\\n.").unwrap_err(),
+            CatalogError::InvalidField { id: 1, .. }
+        ));
+        assert!(matches!(
+            parse("Y.\\n.x").unwrap_err(),
+            CatalogError::InvalidField { id: 1, .. }
+        ));
+        assert!(matches!(
+            parse("Y.\\n.b").unwrap_err(),
+            CatalogError::InvalidField { id: 1, .. }
+        ));
+        assert!(matches!(
+            parse("Y.\\n.G").unwrap_err(),
+            CatalogError::InvalidField { id: 1, .. }
+        ));
+        assert!(matches!(
+            parse_field_catalog("[{}]").unwrap_err(),
+            CatalogError::Json(_)
+        ));
+        assert_eq!(
+            parse_field_catalog(
+                r#"[{"id": 1, "name": "a", "squares": "YB"}, {"id": 1, "name": "b", "squares": "YB"}]"#
+            ),
+            Err(CatalogError::DuplicateId(1))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! デッキの検証。botが選んだデッキが`Environment`のデッキの決まりを守っているかを確かめる。
+use crate::{Card, CardId, Environment};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::{Display, Formatter},
+};
+
+/// `Environment`で指定する追加のデッキの決まり
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct DeckConstraints {
+    /// 同じカードを入れられる最大の枚数。Noneなら制限しない。
+    pub max_copies: Option<usize>,
+    /// デッキに入れられないカード
+    pub banned_cards: BTreeSet<CardId>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum DeckError {
+    /// 枚数が環境の設定と異なる
+    WrongSize { expected: usize, actual: usize },
+    /// カードカタログに存在しないカード
+    UnknownCard { card_id: CardId },
+    /// 重複が許されていないのに同じカードが入っている
+    Duplicate { card_id: CardId },
+    /// 同じカードが最大の枚数より多く入っている
+    TooManyCopies {
+        card_id: CardId,
+        max: usize,
+        actual: usize,
+    },
+    /// 禁止されたカード
+    Banned { card_id: CardId },
+}
+impl Display for DeckError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            DeckError::WrongSize { expected, actual } => {
+                write!(f, "deck size must be {} but was {}", expected, actual)
+            }
+            DeckError::UnknownCard { card_id } => write!(f, "unknown card {}", card_id),
+            DeckError::Duplicate { card_id } => {
+                write!(f, "card {} is picked more than once", card_id)
+            }
+            DeckError::TooManyCopies {
+                card_id,
+                max,
+                actual,
+            } => write!(
+                f,
+                "card {} is picked {} times but at most {} copies are allowed",
+                card_id, actual, max
+            ),
+            DeckError::Banned { card_id } => write!(f, "card {} is banned", card_id),
+        }
+    }
+}
+impl std::error::Error for DeckError {}
+
+/// 選んだカードの並び。`validate`を通ったものだけを対局に使う。
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Deck {
+    pub cards: Vec<CardId>,
+}
+impl Deck {
+    pub fn new(cards: Vec<CardId>) -> Self {
+        Self { cards }
+    }
+    /// 環境のデッキの決まりを守っていればそのまま返し、守っていなければ最初に見つかった違反を返す。
+    pub fn validate(
+        self,
+        env: &Environment,
+        cards: &HashMap<CardId, &Card>,
+    ) -> Result<Deck, DeckError> {
+        if self.cards.len() != env.deck_size {
+            return Err(DeckError::WrongSize {
+                expected: env.deck_size,
+                actual: self.cards.len(),
+            });
+        }
+        let mut copies = BTreeMap::new();
+        for &card_id in self.cards.iter() {
+            if !cards.contains_key(&card_id) {
+                return Err(DeckError::UnknownCard { card_id });
+            }
+            if env.deck_constraints.banned_cards.contains(&card_id) {
+                return Err(DeckError::Banned { card_id });
+            }
+            *copies.entry(card_id).or_insert(0) += 1;
+        }
+        for (&card_id, &actual) in copies.iter() {
+            if !env.is_deplicated_pick_enabled && 1 < actual {
+                return Err(DeckError::Duplicate { card_id });
+            }
+            if let Some(max) = env.deck_constraints.max_copies {
+                if max < actual {
+                    return Err(DeckError::TooManyCopies {
+                        card_id,
+                        max,
+                        actual,
+                    });
+                }
+            }
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CardShape;
+    #[test]
+    fn test_validate() {
+        let card_catalog = [
+            Card::new(1, "a", 1, CardShape::new("Y").unwrap()),
+            Card::new(2, "b", 1, CardShape::new("Y").unwrap()),
+            Card::new(3, "c", 1, CardShape::new("Y").unwrap()),
+        ];
+        let cards = card_catalog
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<_, _>>();
+        let validate = |env: &Environment, cards_in_deck: &[CardId]| {
+            Deck::new(cards_in_deck.to_vec()).validate(env, &cards)
+        };
+
+        let env = Environment::new(2, 3, 1, 3, false);
+        assert!(validate(&env, &[1, 2, 3]).is_ok());
+        assert_eq!(
+            validate(&env, &[1, 2]),
+            Err(DeckError::WrongSize {
+                expected: 3,
+                actual: 2
+            })
+        );
+        assert_eq!(
+            validate(&env, &[1, 2, 4]),
+            Err(DeckError::UnknownCard { card_id: 4 })
+        );
+        assert_eq!(
+            validate(&env, &[1, 2, 2]),
+            Err(DeckError::Duplicate { card_id: 2 })
+        );
+
+        let env = Environment::new(2, 3, 1, 3, true).with_deck_constraints(DeckConstraints {
+            max_copies: Some(2),
+            banned_cards: BTreeSet::from([3]),
+        });
+        assert!(validate(&env, &[1, 2, 2]).is_ok());
+        assert_eq!(
+            validate(&env, &[1, 1, 1]),
+            Err(DeckError::TooManyCopies {
+                card_id: 1,
+                max: 2,
+                actual: 3
+            })
+        );
+        assert_eq!(
+            validate(&env, &[1, 2, 3]),
+            Err(DeckError::Banned { card_id: 3 })
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! 不完全情報の探索のために、観測と矛盾しない完全な局面をランダムに作る。
+//! 相手の手札と山札の順番、自分の山札の順番を決め直した`State`を返すので、
+//! そのまま`State::apply`で先読みできる。
+use crate::{observation::Observation, Card, CardId, Environment, GameError, PlayerState, State};
+use rand::{seq::SliceRandom, Rng};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+/// 観測と矛盾しない局面を1つ作る。
+/// `decks`はプレイヤーごとの宣言されたデッキで、Noneのプレイヤーはカードカタログから
+/// デッキの決まりを守るようにデッキを選び直す。観測しているプレイヤーの分は使わない。
+pub fn determinize(
+    env: &Environment,
+    cards: &HashMap<CardId, &Card>,
+    observation: &Observation,
+    decks: &[Option<Vec<CardId>>],
+    rng: &mut impl Rng,
+) -> Result<State, GameError> {
+    let player_size = observation.played.len();
+    if decks.len() != player_size {
+        return Err(GameError::PlayerCountMismatch {
+            expected: player_size,
+            actual: decks.len(),
+        });
+    }
+    let mut players = vec![];
+    for (player_id, declared) in decks.iter().enumerate() {
+        let (hands, deck) = if player_id == observation.player_id {
+            // 自分の手札は分かっているが、山札の順番は分からない
+            let mut deck = observation.deck.clone();
+            deck.shuffle(rng);
+            (observation.hands.clone(), deck)
+        } else {
+            let played = observation.played_cards(player_id);
+            let deck = match declared {
+                Some(deck) => deck.clone(),
+                None => sample_deck(env, cards, &played, rng)?,
+            };
+            let mut unseen =
+                remove_cards(&deck, &played).ok_or(GameError::DeckMismatch { player_id })?;
+            let hand_size = observation.hand_sizes[player_id];
+            if unseen.len() != hand_size + observation.deck_sizes[player_id] {
+                return Err(GameError::DeckMismatch { player_id });
+            }
+            unseen.shuffle(rng);
+            let deck = unseen.split_off(hand_size);
+            (unseen, deck)
+        };
+        players.push(PlayerState {
+            special_point: observation.special_points[player_id],
+            hands,
+            deck: VecDeque::from(deck),
+            anchors: BTreeSet::new(),
+            special_anchors: BTreeSet::new(),
+            played: observation.played[player_id].clone(),
+        });
+    }
+    Ok(State::from_parts(
+        observation.turn,
+        observation.field.clone(),
+        players,
+    ))
+}
+
+// deckからplayedを1枚ずつ取り除く。deckに無いカードがあればNone。
+fn remove_cards(deck: &[CardId], played: &[CardId]) -> Option<Vec<CardId>> {
+    let mut rest = deck.to_vec();
+    for card_id in played.iter() {
+        let index = rest.iter().position(|c| c == card_id)?;
+        rest.swap_remove(index);
+    }
+    rest.sort();
+    Some(rest)
+}
+
+// 既に出したカードを含み、デッキの決まりを守るデッキをカードカタログから選ぶ
+fn sample_deck(
+    env: &Environment,
+    cards: &HashMap<CardId, &Card>,
+    played: &[CardId],
+    rng: &mut impl Rng,
+) -> Result<Vec<CardId>, GameError> {
+    let max_copies = if env.is_deplicated_pick_enabled {
+        env.deck_constraints.max_copies.unwrap_or(usize::MAX)
+    } else {
+        1
+    };
+    let mut copies = BTreeMap::<CardId, usize>::new();
+    for card_id in played.iter() {
+        *copies.entry(*card_id).or_insert(0) += 1;
+    }
+    // HashMapの順番に依存しないよう、IDの順に並べてから選ぶ
+    let mut candidates = cards
+        .keys()
+        .copied()
+        .filter(|card_id| !env.deck_constraints.banned_cards.contains(card_id))
+        .collect::<Vec<CardId>>();
+    candidates.sort();
+    let mut deck = played.to_vec();
+    while deck.len() < env.deck_size {
+        candidates.retain(|card_id| copies.get(card_id).copied().unwrap_or(0) < max_copies);
+        let Some(&card_id) = candidates.choose(rng) else {
+            return Err(GameError::CatalogTooSmall {
+                deck_size: env.deck_size,
+                available: deck.len(),
+            });
+        };
+        *copies.entry(card_id).or_insert(0) += 1;
+        deck.push(card_id);
+    }
+    Ok(deck)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{catalog::parse_card_catalog, seed::game_rng, Action, Field};
+    #[test]
+    fn test_determinize() {
+        let env = Environment::new(2, 15, 4, 12, false);
+        let mut catalog =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        // 重複なしで15枚のデッキを作れるようにカードを増やす
+        let extra = (1000..1010)
+            .map(|id| {
+                let card = &catalog[id % catalog.len()];
+                Card::new(id, &card.name, card.cost, card.shape.clone())
+            })
+            .collect::<Vec<Card>>();
+        catalog.extend(extra);
+        let cards = catalog
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<_, _>>();
+        let mut ids = cards.keys().copied().collect::<Vec<CardId>>();
+        ids.sort();
+        let decks = vec![ids[..15].to_vec(), ids[4..].to_vec()];
+        let mut state = State::new(&env, &cards, &Field::default(), &decks).unwrap();
+        let mut rng = game_rng(0);
+        for _ in 0..3 {
+            let actions = (0..2)
+                .map(|player_id| {
+                    *state
+                        .generate_valid_actions(&cards, player_id)
+                        .choose(&mut rng)
+                        .unwrap()
+                })
+                .collect::<Vec<Action>>();
+            state.apply(&env, &cards, &actions).unwrap();
+        }
+        let observation = state.observe(0);
+
+        let mut opponent_hands = BTreeSet::new();
+        for declared in [Some(decks[1].clone()), None] {
+            for _ in 0..20 {
+                let mut sampled = determinize(
+                    &env,
+                    &cards,
+                    &observation,
+                    &[None, declared.clone()],
+                    &mut rng,
+                )
+                .unwrap();
+                // 観測は変わらない
+                assert_eq!(sampled.observe(0), observation);
+                assert_eq!(sampled.zobrist_hash(), sampled.compute_zobrist_hash());
+                assert_eq!(
+                    sampled.generate_valid_actions(&cards, 0),
+                    state.generate_valid_actions(&cards, 0)
+                );
+                let opponent = &sampled.players[1];
+                let mut all_cards = opponent
+                    .hands
+                    .iter()
+                    .chain(opponent.deck.iter())
+                    .copied()
+                    .chain(observation.played_cards(1))
+                    .collect::<Vec<CardId>>();
+                all_cards.sort();
+                match &declared {
+                    Some(deck) => assert_eq!(&all_cards, deck),
+                    None => {
+                        // 重複して選べない環境なので同じカードは無い
+                        assert_eq!(all_cards.len(), env.deck_size);
+                        all_cards.dedup();
+                        assert_eq!(all_cards.len(), env.deck_size);
+                    }
+                }
+                opponent_hands.insert(opponent.hands.clone());
+            }
+        }
+        assert!(opponent_hands.len() > 1);
+        // 出したカードが宣言されたデッキに無ければ矛盾している
+        let played = observation.played_cards(1)[0];
+        let wrong_deck = decks[1]
+            .iter()
+            .map(|&card_id| if card_id == played { ids[0] } else { card_id })
+            .collect::<Vec<CardId>>();
+        assert_eq!(
+            determinize(
+                &env,
+                &cards,
+                &observation,
+                &[None, Some(wrong_deck)],
+                &mut rng
+            )
+            .map(|_| ()),
+            Err(GameError::DeckMismatch { player_id: 1 })
+        );
+        // 重複なしで15枚を選べないカタログでは決め直せない
+        let few = ids[..10]
+            .iter()
+            .map(|card_id| (*card_id, cards[card_id]))
+            .collect::<HashMap<_, _>>();
+        assert!(matches!(
+            determinize(&env, &few, &observation, &[None, None], &mut rng),
+            Err(GameError::CatalogTooSmall { deck_size: 15, .. })
+        ));
+    }
+}
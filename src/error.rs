@@ -0,0 +1,175 @@
+use crate::{deck::DeckError, CardId, PlayerId};
+use std::{
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+
+/// ゲームの進行中に起こりうるエラー。
+/// botの不正な入力でjudgeがpanicしないように、検証はすべてこの型で返す。
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum GameError {
+    /// カードカタログに存在しないカード
+    UnknownCard {
+        player_id: PlayerId,
+        card_id: CardId,
+    },
+    /// 手札に存在しないカード
+    CardNotInHand {
+        player_id: PlayerId,
+        card_id: CardId,
+    },
+    /// カードのマスがフィールドの外にはみ出している
+    OutOfField {
+        player_id: PlayerId,
+        card_id: CardId,
+    },
+    /// カードのマスが置けないマスに重なっている
+    Overlap {
+        player_id: PlayerId,
+        card_id: CardId,
+        y: usize,
+        x: usize,
+    },
+    /// 自分のマス（SPECIAL_PUTでは自分のスペシャルマス）に接していない
+    NotAdjacent {
+        player_id: PlayerId,
+        card_id: CardId,
+    },
+    /// スペシャルポイントが足りない
+    NotEnoughSpecialPoints {
+        player_id: PlayerId,
+        card_id: CardId,
+        required: usize,
+        actual: usize,
+    },
+    /// デッキの枚数が環境の設定と異なる
+    InvalidDeckSize {
+        player_id: PlayerId,
+        expected: usize,
+        actual: usize,
+    },
+    /// デッキが環境のデッキの決まりを守っていない
+    InvalidDeck {
+        player_id: PlayerId,
+        reason: DeckError,
+    },
+    /// 配られたカードが選んだデッキと一致しない
+    DeckMismatch { player_id: PlayerId },
+    /// デッキの決まりを守ると、カードカタログからデッキの枚数だけ選べない
+    CatalogTooSmall { deck_size: usize, available: usize },
+    /// フィールドの形が不正
+    InvalidField(String),
+    /// デッキやアクションの数がプレイヤー数と異なる
+    PlayerCountMismatch { expected: usize, actual: usize },
+    /// テキストとして解釈できない入力
+    MalformedText(String),
+    /// botが制限時間内に応答しなかった
+    Timeout(Duration),
+    /// botのプロセスが終了したか、標準出力を閉じた
+    BotExited(String),
+    /// botのプロセスを起動できない
+    SpawnFailed(String),
+}
+impl GameError {
+    /// エラーの原因となったプレイヤー。特定できない場合はNone。
+    pub fn player_id(&self) -> Option<PlayerId> {
+        match self {
+            GameError::UnknownCard { player_id, .. }
+            | GameError::CardNotInHand { player_id, .. }
+            | GameError::OutOfField { player_id, .. }
+            | GameError::Overlap { player_id, .. }
+            | GameError::NotAdjacent { player_id, .. }
+            | GameError::NotEnoughSpecialPoints { player_id, .. }
+            | GameError::InvalidDeckSize { player_id, .. }
+            | GameError::InvalidDeck { player_id, .. }
+            | GameError::DeckMismatch { player_id } => Some(*player_id),
+            GameError::InvalidField(_)
+            | GameError::CatalogTooSmall { .. }
+            | GameError::PlayerCountMismatch { .. }
+            | GameError::MalformedText(_)
+            | GameError::Timeout(_)
+            | GameError::BotExited(_)
+            | GameError::SpawnFailed(_) => None,
+        }
+    }
+    /// botの行動の誤りではなく、botが応答しなくなったことによるエラーか
+    pub fn is_bot_failure(&self) -> bool {
+        matches!(self, GameError::Timeout(_) | GameError::BotExited(_))
+    }
+}
+impl Display for GameError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            GameError::UnknownCard { player_id, card_id } => {
+                write!(f, "player {}: unknown card {}", player_id, card_id)
+            }
+            GameError::CardNotInHand { player_id, card_id } => {
+                write!(f, "player {}: card {} is not in hand", player_id, card_id)
+            }
+            GameError::OutOfField { player_id, card_id } => {
+                write!(f, "player {}: card {} is out of field", player_id, card_id)
+            }
+            GameError::Overlap {
+                player_id,
+                card_id,
+                y,
+                x,
+            } => write!(
+                f,
+                "player {}: card {} overlaps the square ({}, {})",
+                player_id, card_id, y, x
+            ),
+            GameError::NotAdjacent { player_id, card_id } => write!(
+                f,
+                "player {}: card {} is not adjacent to own squares",
+                player_id, card_id
+            ),
+            GameError::NotEnoughSpecialPoints {
+                player_id,
+                card_id,
+                required,
+                actual,
+            } => write!(
+                f,
+                "player {}: card {} requires {} special points but has {}",
+                player_id, card_id, required, actual
+            ),
+            GameError::InvalidDeckSize {
+                player_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "player {}: deck size must be {} but was {}",
+                player_id, expected, actual
+            ),
+            GameError::InvalidDeck { player_id, reason } => {
+                write!(f, "player {}: invalid deck: {}", player_id, reason)
+            }
+            GameError::DeckMismatch { player_id } => write!(
+                f,
+                "player {}: dealt cards differ from the picked deck",
+                player_id
+            ),
+            GameError::CatalogTooSmall {
+                deck_size,
+                available,
+            } => write!(
+                f,
+                "catalog too small to determinize a {}-card deck: only {} cards can be picked",
+                deck_size, available
+            ),
+            GameError::InvalidField(reason) => write!(f, "invalid field: {}", reason),
+            GameError::PlayerCountMismatch { expected, actual } => write!(
+                f,
+                "expected {} entries (one per player) but got {}",
+                expected, actual
+            ),
+            GameError::MalformedText(s) => write!(f, "malformed text: {:?}", s),
+            GameError::Timeout(limit) => write!(f, "no response within {:?}", limit),
+            GameError::BotExited(reason) => write!(f, "bot exited: {}", reason),
+            GameError::SpawnFailed(reason) => write!(f, "couldn't spawn bot: {}", reason),
+        }
+    }
+}
+impl std::error::Error for GameError {}
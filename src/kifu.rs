@@ -0,0 +1,709 @@
+//! 棋譜ファイル。対局の設定から各ターンの結果までを、バージョン付きのJSONとして読み書きする。
+//! judgeが以前から書き出しているテキストの棋譜も読み込んで同じ形に変換できる。
+use crate::{
+    catalog::{CardJson, FieldJson},
+    deck::DeckConstraints,
+    record::{GameRecord, MoveLog},
+    rules::Rules,
+    setup::{GameSetup, SetupPhase},
+    Action, Card, CardId, Environment, Field, FieldShape, MulliganAction, PlayerId, State,
+    TurnOutcome, MAX_PLAYER_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::{Display, Formatter},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// このライブラリが読み書きする棋譜の形式のバージョン
+pub const KIFU_VERSION: u32 = 1;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum KifuError {
+    /// ファイルが読み書きできない
+    Io { path: String, reason: String },
+    /// JSONとして解釈できない
+    Json(String),
+    /// 読めない新しいバージョンの棋譜
+    UnsupportedVersion(u32),
+    /// 対局の記録として成り立たない
+    Invalid(String),
+}
+impl Display for KifuError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            KifuError::Io { path, reason } => write!(f, "couldn't access {}: {}", path, reason),
+            KifuError::Json(reason) => write!(f, "invalid JSON: {}", reason),
+            KifuError::UnsupportedVersion(version) => write!(
+                f,
+                "kifu version {} is newer than supported version {}",
+                version, KIFU_VERSION
+            ),
+            KifuError::Invalid(reason) => write!(f, "invalid kifu: {}", reason),
+        }
+    }
+}
+impl std::error::Error for KifuError {}
+
+/// 対局したbot
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Default)]
+pub struct BotInfo {
+    pub name: String,
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+/// 対局そのものではない付随情報
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Default)]
+pub struct Metadata {
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// 対局を終えた日時。RFC 3339形式のUTC。
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(default)]
+    pub judge_version: Option<String>,
+    pub bots: Vec<BotInfo>,
+}
+
+/// `Environment`の設定
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct EnvironmentJson {
+    pub player_size: usize,
+    pub deck_size: usize,
+    pub hand_size: usize,
+    pub max_turn: usize,
+    pub duplicated_pick: bool,
+    pub rules: String,
+    #[serde(default)]
+    pub max_copies: Option<usize>,
+    #[serde(default)]
+    pub banned_cards: Vec<CardId>,
+}
+impl From<&Environment> for EnvironmentJson {
+    fn from(env: &Environment) -> Self {
+        Self {
+            player_size: env.player_size,
+            deck_size: env.deck_size,
+            hand_size: env.hand_size,
+            max_turn: env.max_turn,
+            duplicated_pick: env.is_deplicated_pick_enabled,
+            rules: env.rules.to_string(),
+            max_copies: env.deck_constraints.max_copies,
+            banned_cards: env.deck_constraints.banned_cards.iter().copied().collect(),
+        }
+    }
+}
+impl EnvironmentJson {
+    /// `Environment::new`のassertに当たる設定はエラーにする
+    pub fn to_environment(&self) -> Result<Environment, KifuError> {
+        if !(1..=MAX_PLAYER_SIZE).contains(&self.player_size) {
+            return Err(KifuError::Invalid(format!(
+                "player size {} is out of range",
+                self.player_size
+            )));
+        }
+        if self.deck_size + 1 < self.max_turn + self.hand_size {
+            return Err(KifuError::Invalid(format!(
+                "deck size {} is too small for {} turns with {} cards in hand",
+                self.deck_size, self.max_turn, self.hand_size
+            )));
+        }
+        let rules = self.rules.parse::<Rules>().map_err(KifuError::Invalid)?;
+        Ok(Environment::new(
+            self.player_size,
+            self.deck_size,
+            self.hand_size,
+            self.max_turn,
+            self.duplicated_pick,
+        )
+        .with_rules(rules)
+        .with_deck_constraints(DeckConstraints {
+            max_copies: self.max_copies,
+            banned_cards: self.banned_cards.iter().copied().collect(),
+        }))
+    }
+}
+
+/// 1ターン分の記録
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct KifuTurn {
+    /// プレイヤー順のアクション。反則で終わったターンは途中までになる。
+    pub actions: Vec<Action>,
+    /// `State::apply`の結果。テキストの棋譜から変換した場合などは無い。
+    #[serde(default)]
+    pub outcome: Option<TurnOutcome>,
+    #[serde(default)]
+    pub logs: Vec<Option<MoveLog>>,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct KifuForfeit {
+    pub player_id: PlayerId,
+    pub reason: String,
+}
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct KifuFailure {
+    pub player_id: PlayerId,
+    pub turn: usize,
+    pub reason: String,
+}
+
+/// 対局の結果
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct KifuResult {
+    pub scores: Vec<usize>,
+    pub ranking: Vec<usize>,
+    pub winner: Option<PlayerId>,
+    #[serde(default)]
+    pub forfeit: Option<KifuForfeit>,
+    #[serde(default)]
+    pub failures: Vec<KifuFailure>,
+    #[serde(default)]
+    pub exit_statuses: Vec<Option<String>>,
+}
+
+/// 1局分の棋譜。各項目の意味は`GameRecord`と同じで、対局の設定と使ったカードも含む。
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct Kifu {
+    pub version: u32,
+    pub metadata: Metadata,
+    pub environment: EnvironmentJson,
+    pub field: FieldJson,
+    /// 選ばれたデッキに入っているカード
+    pub cards: Vec<CardJson>,
+    pub picked_decks: Vec<Vec<CardId>>,
+    pub shuffled_decks: Vec<Vec<CardId>>,
+    pub mulligans: Vec<MulliganAction>,
+    pub decks: Vec<Vec<CardId>>,
+    #[serde(default)]
+    pub deck_logs: Vec<Option<MoveLog>>,
+    #[serde(default)]
+    pub mulligan_logs: Vec<Option<MoveLog>>,
+    pub turns: Vec<KifuTurn>,
+    /// テキストの棋譜から変換した場合は無い
+    #[serde(default)]
+    pub result: Option<KifuResult>,
+}
+impl Kifu {
+    /// `Match::run`の記録から作る。メタデータは種とbot名だけを入れる。
+    pub fn new(env: &Environment, field: &Field, catalog: &[Card], record: &GameRecord) -> Self {
+        let used = record
+            .picked_decks
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<BTreeSet<CardId>>();
+        let turns = record
+            .turns
+            .iter()
+            .enumerate()
+            .map(|(index, actions)| KifuTurn {
+                actions: actions.clone(),
+                outcome: record.outcomes.get(index).cloned(),
+                logs: record.turn_logs.get(index).cloned().unwrap_or_default(),
+            })
+            .collect();
+        Self {
+            version: KIFU_VERSION,
+            metadata: Metadata {
+                seed: Some(record.seed),
+                bots: record
+                    .names
+                    .iter()
+                    .map(|name| BotInfo {
+                        name: name.clone(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            environment: EnvironmentJson::from(env),
+            field: FieldJson::from(field),
+            cards: catalog
+                .iter()
+                .filter(|card| used.contains(&card.id))
+                .map(CardJson::from)
+                .collect(),
+            picked_decks: record.picked_decks.clone(),
+            shuffled_decks: record.shuffled_decks.clone(),
+            mulligans: record.mulligans.clone(),
+            decks: record.decks.clone(),
+            deck_logs: record.deck_logs.clone(),
+            mulligan_logs: record.mulligan_logs.clone(),
+            turns,
+            result: Some(KifuResult {
+                scores: record.scores.clone(),
+                ranking: record.ranking.clone(),
+                winner: record.winner,
+                forfeit: record.forfeit.as_ref().map(|forfeit| KifuForfeit {
+                    player_id: forfeit.player_id,
+                    reason: forfeit.reason.to_string(),
+                }),
+                failures: record
+                    .failures
+                    .iter()
+                    .map(|failure| KifuFailure {
+                        player_id: failure.player_id,
+                        turn: failure.turn,
+                        reason: failure.reason.to_string(),
+                    })
+                    .collect(),
+                exit_statuses: record.exit_statuses.clone(),
+            }),
+        }
+    }
+    pub fn environment(&self) -> Result<Environment, KifuError> {
+        self.environment.to_environment()
+    }
+    pub fn field(&self) -> Result<Field, KifuError> {
+        Ok(Field {
+            id: self.field.id,
+            name: self.field.name.clone(),
+            shape: FieldShape::new(&self.field.squares)
+                .map_err(|why| KifuError::Invalid(format!("field: {}", why)))?,
+        })
+    }
+    pub fn cards(&self) -> Result<Vec<Card>, KifuError> {
+        self.cards
+            .iter()
+            .map(|card| {
+                card.to_recorded_card()
+                    .map_err(|why| KifuError::Invalid(why.to_string()))
+            })
+            .collect()
+    }
+    /// プレイヤーごとの記録がプレイヤーの人数より多くないことを確かめる。
+    /// 対局が反則で終わっていると、反則より後の記録は少ないことがある。
+    pub fn check_player_size(&self, env: &Environment) -> Result<(), KifuError> {
+        let mut lengths = vec![
+            ("bots", self.metadata.bots.len()),
+            ("picked decks", self.picked_decks.len()),
+            ("shuffled decks", self.shuffled_decks.len()),
+            ("mulligans", self.mulligans.len()),
+            ("decks", self.decks.len()),
+            ("deck logs", self.deck_logs.len()),
+            ("mulligan logs", self.mulligan_logs.len()),
+        ];
+        if let Some(result) = &self.result {
+            lengths.push(("scores", result.scores.len()));
+            lengths.push(("ranks", result.ranking.len()));
+        }
+        for (what, len) in lengths {
+            if len > env.player_size {
+                return Err(KifuError::Invalid(format!(
+                    "{} {} for {} players",
+                    len, what, env.player_size
+                )));
+            }
+        }
+        for (index, turn) in self.turns.iter().enumerate() {
+            let len = turn.actions.len().max(turn.logs.len());
+            if len > env.player_size {
+                return Err(KifuError::Invalid(format!(
+                    "turn {}: {} actions for {} players",
+                    index + 1,
+                    len,
+                    env.player_size
+                )));
+            }
+        }
+        Ok(())
+    }
+    /// 記録された山札の順番で配り直し、1ターン目が始まる前の状態を作る
+    pub fn start(
+        &self,
+        env: &Environment,
+        cards: &HashMap<CardId, &Card>,
+        field: &Field,
+    ) -> Result<State, KifuError> {
+        let invalid = |player_id: PlayerId, why: String| {
+            KifuError::Invalid(format!("player {}: {}", player_id, why))
+        };
+        let missing = |player_id: PlayerId, what: &str| invalid(player_id, format!("no {}", what));
+        self.check_player_size(env)?;
+        let mut setup = GameSetup::new(env);
+        for player_id in 0..env.player_size {
+            let deck = self
+                .picked_decks
+                .get(player_id)
+                .ok_or_else(|| missing(player_id, "picked deck"))?;
+            setup
+                .pick_deck(env, cards, player_id, deck)
+                .map_err(|why| invalid(player_id, why.to_string()))?;
+        }
+        for player_id in 0..env.player_size {
+            let shuffled = self
+                .shuffled_decks
+                .get(player_id)
+                .ok_or_else(|| missing(player_id, "shuffled deck"))?;
+            setup
+                .deal(player_id, shuffled)
+                .map_err(|why| invalid(player_id, why.to_string()))?;
+            let mulligan = *self
+                .mulligans
+                .get(player_id)
+                .ok_or_else(|| missing(player_id, "mulligan"))?;
+            setup.mulligan(player_id, mulligan);
+            let deck = self
+                .decks
+                .get(player_id)
+                .ok_or_else(|| missing(player_id, "deck"))?;
+            if setup.phase(player_id) == SetupPhase::Redeal {
+                setup
+                    .deal(player_id, deck)
+                    .map_err(|why| invalid(player_id, why.to_string()))?;
+            } else if setup.deck(player_id) != deck.as_slice() {
+                return Err(invalid(
+                    player_id,
+                    "deck changed without mulligan".to_string(),
+                ));
+            }
+        }
+        setup
+            .into_state(env, cards, field)
+            .map_err(|why| KifuError::Invalid(why.to_string()))
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("棋譜はJSONにできる")
+    }
+}
+
+/// JSONの棋譜を読む。先頭が`{`でなければテキストの棋譜として変換する。
+pub fn parse_kifu(text: &str) -> Result<Kifu, KifuError> {
+    if !text.trim_start().starts_with('{') {
+        return parse_legacy_kifu(text);
+    }
+    // 新しいバージョンの棋譜は中身を読む前に分かりやすいエラーにする
+    #[derive(Deserialize)]
+    struct Version {
+        version: u32,
+    }
+    let version = serde_json::from_str::<Version>(text)
+        .map_err(|why| KifuError::Json(why.to_string()))?
+        .version;
+    if version > KIFU_VERSION {
+        return Err(KifuError::UnsupportedVersion(version));
+    }
+    serde_json::from_str(text).map_err(|why| KifuError::Json(why.to_string()))
+}
+pub fn load_kifu(path: impl AsRef<Path>) -> Result<Kifu, KifuError> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|why| KifuError::Io {
+        path: path.display().to_string(),
+        reason: why.to_string(),
+    })?;
+    parse_kifu(&text)
+}
+pub fn write_kifu(path: impl AsRef<Path>, kifu: &Kifu) -> Result<(), KifuError> {
+    let path = path.as_ref();
+    std::fs::write(path, kifu.to_json() + "\n").map_err(|why| KifuError::Io {
+        path: path.display().to_string(),
+        reason: why.to_string(),
+    })
+}
+
+// テキストの棋譜を1行ずつ読む
+struct LegacyLines<'a> {
+    lines: Vec<&'a str>,
+    index: usize,
+}
+impl<'a> LegacyLines<'a> {
+    fn malformed(&self, reason: &str) -> KifuError {
+        KifuError::Invalid(format!("line {}: {}", self.index, reason))
+    }
+    fn try_line(&mut self) -> Option<&'a str> {
+        let line = self.lines.get(self.index)?;
+        self.index += 1;
+        Some(line)
+    }
+    fn line(&mut self) -> Result<&'a str, KifuError> {
+        self.try_line()
+            .ok_or_else(|| self.malformed("unexpected end of kifu"))
+    }
+    fn numbers(&mut self, line: &str) -> Result<Vec<usize>, KifuError> {
+        line.split_whitespace()
+            .map(|word| word.parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|_| self.malformed("expected numbers"))
+    }
+    fn next_numbers(&mut self, n: usize) -> Result<Vec<usize>, KifuError> {
+        let line = self.line()?;
+        let numbers = self.numbers(line)?;
+        if numbers.len() < n {
+            return Err(self.malformed(&format!("expected {} numbers", n)));
+        }
+        Ok(numbers)
+    }
+    fn rows(&mut self, n: usize) -> Result<String, KifuError> {
+        (0..n)
+            .map(|_| self.line().map(str::trim))
+            .collect::<Result<Vec<&str>, _>>()
+            .map(|rows| rows.join("\n"))
+    }
+}
+
+/// `text_protocol::format_record`が書き出すテキストの棋譜を変換する。
+/// テキストにはフィールドとカードの名前やルールの選択が無いので、名前は空、ルールは既定値になる。
+/// 反則で途中で終わった棋譜は、書かれているところまでを読む。
+pub fn parse_legacy_kifu(text: &str) -> Result<Kifu, KifuError> {
+    let mut lines = LegacyLines {
+        lines: text.lines().collect(),
+        index: 0,
+    };
+    let mut metadata = Metadata::default();
+    let mut line = lines.line()?;
+    if let Some(seed) = line.strip_prefix("SEED ") {
+        metadata.seed = Some(
+            seed.trim()
+                .parse()
+                .map_err(|_| lines.malformed("invalid seed"))?,
+        );
+        line = lines.line()?;
+    }
+    let header = lines.numbers(line)?;
+    if header.len() < 5 {
+        return Err(lines.malformed("expected 5 numbers"));
+    }
+    let player_size = header[0];
+    let environment = EnvironmentJson {
+        player_size,
+        deck_size: header[1],
+        hand_size: header[2],
+        max_turn: header[3],
+        duplicated_pick: header[4] == 1,
+        rules: Rules::default().to_string(),
+        max_copies: None,
+        banned_cards: vec![],
+    };
+    let field_size = lines.next_numbers(2)?;
+    let field = FieldJson {
+        id: 0,
+        name: String::new(),
+        squares: lines.rows(field_size[0])?,
+    };
+    let n_cards = lines.next_numbers(1)?[0];
+    let mut cards = vec![];
+    for _ in 0..n_cards {
+        let card = lines.next_numbers(4)?;
+        cards.push(CardJson {
+            id: card[0],
+            name: String::new(),
+            cost: card[1],
+            squares: lines.rows(card[2])?,
+        });
+    }
+    for _ in 0..player_size {
+        metadata.bots.push(BotInfo {
+            name: lines.line()?.trim().to_string(),
+            ..Default::default()
+        });
+    }
+
+    // ここから先は反則で終わった対局では途中までしか書かれていない
+    let mut picked_decks = vec![];
+    while picked_decks.len() < player_size {
+        let Some(line) = lines.try_line() else { break };
+        picked_decks.push(lines.numbers(line)?);
+    }
+    let mut shuffled_decks = vec![];
+    let mut mulligans = vec![];
+    while shuffled_decks.len() < player_size {
+        let Some(line) = lines.try_line() else { break };
+        shuffled_decks.push(lines.numbers(line)?);
+        let Some(line) = lines.try_line() else { break };
+        mulligans.push(
+            line.parse::<MulliganAction>()
+                .map_err(|why| lines.malformed(&why.to_string()))?,
+        );
+    }
+    let mut decks = vec![];
+    while decks.len() < player_size {
+        let Some(line) = lines.try_line() else { break };
+        decks.push(lines.numbers(line)?);
+    }
+    let mut actions = vec![];
+    while let Some(line) = lines.try_line() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        actions.push(
+            line.parse::<Action>()
+                .map_err(|why| lines.malformed(&why.to_string()))?,
+        );
+    }
+    let turns = actions
+        .chunks(player_size.max(1))
+        .map(|actions| KifuTurn {
+            actions: actions.to_vec(),
+            outcome: None,
+            logs: vec![],
+        })
+        .collect();
+
+    Ok(Kifu {
+        version: KIFU_VERSION,
+        metadata,
+        environment,
+        field,
+        cards,
+        picked_decks,
+        shuffled_decks,
+        mulligans,
+        decks,
+        deck_logs: vec![],
+        mulligan_logs: vec![],
+        turns,
+        result: None,
+    })
+}
+
+/// 日時をRFC 3339形式のUTCで表す。棋譜の`Metadata::date`に使う。
+pub fn format_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    // 1970-01-01からの日数をグレゴリオ暦の年月日に直す
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::{Agent, RandomAgent},
+        catalog::parse_card_catalog,
+        match_runner::Match,
+        seed::game_rng,
+        text_protocol::format_record,
+        CardSquareType,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_kifu() {
+        let env = Environment::new(2, 6, 4, 3, false);
+        let catalog =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        let field = Field::default();
+        let mut agents: Vec<Box<dyn Agent>> = vec![
+            Box::new(RandomAgent::new("a", game_rng(0))),
+            Box::new(RandomAgent::new("b", game_rng(1))),
+        ];
+        let record = Match::run(&env, &field, &catalog, &mut agents, 3).unwrap();
+        let kifu = Kifu::new(&env, &field, &catalog, &record);
+        assert_eq!(parse_kifu(&kifu.to_json()), Ok(kifu.clone()));
+        assert_eq!(kifu.turns.len(), env.max_turn);
+        assert!(kifu.turns.iter().all(|turn| turn.outcome.is_some()));
+
+        // テキストの棋譜から変換しても対局の中身は同じになる
+        let legacy = parse_kifu(&format_record(&env, &catalog, &field.shape, &record)).unwrap();
+        assert_eq!(legacy.metadata.seed, Some(3));
+        assert_eq!(legacy.metadata.bots[1].name, "b");
+        assert_eq!(legacy.picked_decks, kifu.picked_decks);
+        assert_eq!(legacy.shuffled_decks, kifu.shuffled_decks);
+        assert_eq!(legacy.mulligans, kifu.mulligans);
+        assert_eq!(legacy.decks, kifu.decks);
+        assert_eq!(
+            legacy
+                .turns
+                .iter()
+                .map(|turn| turn.actions.clone())
+                .collect::<Vec<_>>(),
+            record.turns
+        );
+        assert_eq!(legacy.field.squares, kifu.field.squares);
+        assert!(legacy.result.is_none());
+
+        // どちらからでも同じ盤面で対局を始められる
+        let cards = kifu.cards().unwrap();
+        let cards = cards
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<_, _>>();
+        let legacy_cards = legacy.cards().unwrap();
+        let legacy_cards = legacy_cards
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<_, _>>();
+        let state = kifu
+            .start(&kifu.environment().unwrap(), &cards, &kifu.field().unwrap())
+            .unwrap();
+        let legacy_state = legacy
+            .start(
+                &legacy.environment().unwrap(),
+                &legacy_cards,
+                &legacy.field().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(state.zobrist_hash(), legacy_state.zobrist_hash());
+
+        let mut newer = kifu.clone();
+        newer.version = KIFU_VERSION + 1;
+        assert_eq!(
+            parse_kifu(&newer.to_json()),
+            Err(KifuError::UnsupportedVersion(KIFU_VERSION + 1))
+        );
+        assert!(matches!(
+            parse_legacy_kifu("SEED 1\n2 6 4 3 0\n"),
+            Err(KifuError::Invalid(_))
+        ));
+    }
+    #[test]
+    fn test_baseline_kifu() {
+        // 以前のjudgeが書いた棋譜。カード1のスペシャルマスが2つある
+        let kifu = parse_kifu(include_str!("../resources/kifu_sample_baseline.txt")).unwrap();
+        let cards = kifu.cards().unwrap();
+        let card = cards.iter().find(|card| card.id == 1).unwrap();
+        assert_eq!(
+            card.shape
+                .squares
+                .iter()
+                .flatten()
+                .filter(|&&square| square == CardSquareType::Special)
+                .count(),
+            2
+        );
+        let cards = cards
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<_, _>>();
+        let env = kifu.environment().unwrap();
+        let state = kifu.start(&env, &cards, &kifu.field().unwrap()).unwrap();
+        assert_eq!(state.players[0].hands, vec![23, 3, 11, 34]);
+
+        let mut empty = kifu.cards[0].clone();
+        empty.squares = "..\n..".to_string();
+        assert!(empty.to_recorded_card().is_err());
+    }
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_date(UNIX_EPOCH + Duration::from_secs(1_709_251_199)),
+            "2024-02-29T23:59:59Z"
+        );
+    }
+}
@@ -1,16 +1,36 @@
-use log::{debug, error};
-use std::convert::From;
+use log::debug;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeSet, HashMap, VecDeque},
     fmt::{Display, Formatter},
+    str::FromStr,
 };
 
+pub mod agent;
+pub mod bitboard;
+pub mod catalog;
+pub mod deck;
+pub mod determinization;
+mod error;
+pub mod kifu;
+pub mod match_runner;
+pub mod observation;
+pub mod record;
+pub mod rules;
+pub mod seed;
+pub mod setup;
 pub mod text_protocol;
+pub mod tournament;
+pub mod verify;
+mod zobrist;
+use bitboard::{Bitboard, CardMask};
+use deck::DeckConstraints;
+pub use error::GameError;
+use rules::{Overlap, Paint, RuleSet, Rules};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 pub type CardId = usize;
 pub type FieldId = usize;
 pub type PlayerId = usize;
 
-const DYDX4: [(usize, usize); 4] = [(!0, 0), (0, !0), (0, 1), (1, 0)];
 const DYDX8: [(usize, usize); 8] = [
     (!0, !0),
     (!0, 0),
@@ -22,12 +42,21 @@ const DYDX8: [(usize, usize); 8] = [
     (1, 1),
 ];
 
+/// 対戦できる最大の人数
+pub const MAX_PLAYER_SIZE: usize = 4;
+/// プレイヤーごとのマスの文字。スペシャルマスは大文字で表す。
+pub const PLAYER_CHARS: [char; MAX_PLAYER_SIZE] = ['y', 'b', 'g', 'p'];
+
 pub struct Environment {
     pub player_size: usize,
     pub hand_size: usize,
     pub max_turn: usize,
     pub deck_size: usize,
     pub is_deplicated_pick_enabled: bool,
+    /// 解釈の分かれるルールの選択
+    pub rules: Rules,
+    /// 重複の可否以外のデッキの決まり
+    pub deck_constraints: DeckConstraints,
 }
 impl Environment {
     pub fn new(
@@ -37,6 +66,7 @@ impl Environment {
         max_turn: usize,
         is_deplicated_pick_enabled: bool,
     ) -> Self {
+        assert!((1..=MAX_PLAYER_SIZE).contains(&player_size));
         assert!(max_turn + hand_size <= deck_size + 1);
         Self {
             player_size,
@@ -44,8 +74,22 @@ impl Environment {
             max_turn,
             deck_size,
             is_deplicated_pick_enabled,
+            rules: Rules::Official,
+            deck_constraints: DeckConstraints::default(),
         }
     }
+    pub fn with_rules(self, rules: Rules) -> Self {
+        Self { rules, ..self }
+    }
+    pub fn with_deck_constraints(self, deck_constraints: DeckConstraints) -> Self {
+        Self {
+            deck_constraints,
+            ..self
+        }
+    }
+    pub fn rule_set(&self) -> &'static dyn RuleSet {
+        self.rules.rule_set()
+    }
 }
 
 #[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
@@ -63,23 +107,11 @@ pub enum FieldSquareType {
 impl FieldSquareType {
     pub fn to_char(&self) -> char {
         match &self {
-            FieldSquareType::Colored { player_id } => {
-                if *player_id == 0 {
-                    'y'
-                } else {
-                    'b'
-                }
-            }
+            FieldSquareType::Colored { player_id } => PLAYER_CHARS[*player_id],
             FieldSquareType::Special {
                 player_id,
                 activeted: _,
-            } => {
-                if *player_id == 0 {
-                    'Y'
-                } else {
-                    'B'
-                }
-            }
+            } => PLAYER_CHARS[*player_id].to_ascii_uppercase(),
             FieldSquareType::Block => '#',
             FieldSquareType::Empty => '.',
         }
@@ -99,25 +131,25 @@ impl FieldSquareType {
         }
     }
 }
-impl From<char> for FieldSquareType {
-    fn from(item: char) -> Self {
-        match item {
-            'y' => FieldSquareType::Colored { player_id: 0 },
-            'Y' => FieldSquareType::Special {
-                player_id: 0,
-                activeted: false,
-            },
-            'b' => FieldSquareType::Colored { player_id: 1 },
-            'B' => FieldSquareType::Special {
-                player_id: 1,
+impl TryFrom<char> for FieldSquareType {
+    type Error = GameError;
+    fn try_from(item: char) -> Result<Self, Self::Error> {
+        if let Some(player_id) = PLAYER_CHARS.iter().position(|&c| c == item) {
+            return Ok(FieldSquareType::Colored { player_id });
+        }
+        if let Some(player_id) = PLAYER_CHARS
+            .iter()
+            .position(|&c| c.to_ascii_uppercase() == item)
+        {
+            return Ok(FieldSquareType::Special {
+                player_id,
                 activeted: false,
-            },
-            '#' => FieldSquareType::Block,
-            '.' => FieldSquareType::Empty,
-            _ => {
-                error!("invalid input {}", item);
-                unreachable!()
-            }
+            });
+        }
+        match item {
+            '#' => Ok(FieldSquareType::Block),
+            '.' => Ok(FieldSquareType::Empty),
+            _ => Err(GameError::MalformedText(item.to_string())),
         }
     }
 }
@@ -142,16 +174,14 @@ impl CardSquareType {
         }
     }
 }
-impl From<char> for CardSquareType {
-    fn from(item: char) -> Self {
+impl TryFrom<char> for CardSquareType {
+    type Error = GameError;
+    fn try_from(item: char) -> Result<Self, Self::Error> {
         match item {
-            'y' => CardSquareType::Colored,
-            'Y' => CardSquareType::Special,
-            '.' => CardSquareType::Empty,
-            _ => {
-                error!("invalid input {}", item);
-                unreachable!()
-            }
+            'y' => Ok(CardSquareType::Colored),
+            'Y' => Ok(CardSquareType::Special),
+            '.' => Ok(CardSquareType::Empty),
+            _ => Err(GameError::MalformedText(item.to_string())),
         }
     }
 }
@@ -162,6 +192,25 @@ impl Display for CardSquareType {
     }
 }
 
+// 改行区切りの文字列を矩形の2次元配列として解釈する
+fn parse_rows<T>(
+    item: &str,
+    parse: impl Fn(char) -> Result<T, GameError>,
+) -> Result<Vec<Vec<T>>, GameError> {
+    let mut squares: Vec<Vec<T>> = vec![];
+    for row in item.split('\n') {
+        squares.push(
+            row.chars()
+                .map(&parse)
+                .collect::<Result<Vec<T>, GameError>>()?,
+        );
+    }
+    if squares[0].is_empty() || squares.iter().any(|row| row.len() != squares[0].len()) {
+        return Err(GameError::MalformedText(item.to_string()));
+    }
+    Ok(squares)
+}
+
 #[derive(Eq, PartialEq, Debug, Clone, Hash)]
 pub struct FieldShape {
     pub height: usize,
@@ -169,16 +218,13 @@ pub struct FieldShape {
     pub squares: Vec<Vec<FieldSquareType>>,
 }
 impl FieldShape {
-    pub fn new(item: &str) -> Self {
-        let mut squares: Vec<Vec<FieldSquareType>> = vec![];
-        for row in item.split('\n') {
-            squares.push(row.chars().map(FieldSquareType::from).collect())
-        }
-        Self {
+    pub fn new(item: &str) -> Result<Self, GameError> {
+        let squares = parse_rows(item, FieldSquareType::try_from)?;
+        Ok(Self {
             height: squares.len(),
             width: squares[0].len(),
             squares,
-        }
+        })
     }
     fn count_squares(&self, field_square_types: &[FieldSquareType]) -> usize {
         let mut count = 0;
@@ -222,17 +268,20 @@ pub struct CardShape {
     pub width: usize,
     pub squares: Vec<Vec<CardSquareType>>,
 }
+impl FromStr for FieldShape {
+    type Err = GameError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
 impl CardShape {
-    pub fn new(item: &str) -> Self {
-        let mut squares: Vec<Vec<CardSquareType>> = vec![];
-        for row in item.split('\n') {
-            squares.push(row.chars().map(CardSquareType::from).collect())
-        }
-        Self {
+    pub fn new(item: &str) -> Result<Self, GameError> {
+        let squares = parse_rows(item, CardSquareType::try_from)?;
+        Ok(Self {
             height: squares.len(),
             width: squares[0].len(),
             squares,
-        }
+        })
     }
     pub fn trim(seed: &CardShape) -> CardShape {
         let mut min_y = seed.height;
@@ -314,6 +363,12 @@ impl CardShape {
         }
     }
 }
+impl FromStr for CardShape {
+    type Err = GameError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
 impl std::fmt::Display for CardShape {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         let s = self
@@ -326,6 +381,48 @@ impl std::fmt::Display for CardShape {
     }
 }
 
+/// 向きごとに回転済みのカードの形。
+/// `squares`は基準点からの相対座標(dy, dx)と塗るマスの種類で、空白マスは含まない。
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub struct OrientedShape {
+    pub dir: Direction,
+    pub shape: CardShape,
+    pub reference_point: (usize, usize),
+    pub squares: Vec<(isize, isize, CardSquareType)>,
+    pub mask: CardMask,
+}
+impl OrientedShape {
+    fn new(shape: &CardShape, dir: Direction) -> Self {
+        let mut rotated = shape.clone();
+        for _ in 0..dir.rotation_count() {
+            rotated = rotated.rotate();
+        }
+        let (ry, rx) = rotated.find_reference_point(0);
+        let mut squares = vec![];
+        for i in 0..rotated.height {
+            for j in 0..rotated.width {
+                if matches!(
+                    rotated.squares[i][j],
+                    CardSquareType::Colored | CardSquareType::Special
+                ) {
+                    squares.push((
+                        i as isize - ry as isize,
+                        j as isize - rx as isize,
+                        rotated.squares[i][j],
+                    ));
+                }
+            }
+        }
+        Self {
+            dir,
+            mask: CardMask::new(&rotated),
+            shape: rotated,
+            reference_point: (ry, rx),
+            squares,
+        }
+    }
+}
+
 #[derive(Eq, PartialEq, Hash)]
 pub struct Card {
     pub id: CardId,
@@ -333,6 +430,8 @@ pub struct Card {
     pub cost: usize,
     pub power: usize,
     pub shape: CardShape,
+    /// `Direction::ALL`の順に並んだ回転済みの形。盤面の判定・更新・描画はすべてここを参照する。
+    pub orientations: [OrientedShape; 4],
 }
 impl Card {
     pub fn new(id: CardId, name: &str, cost: usize, shape: CardShape) -> Self {
@@ -341,30 +440,51 @@ impl Card {
             name: String::from(name),
             cost,
             power: shape.count_colored_squares(),
+            orientations: Direction::ALL.map(|dir| OrientedShape::new(&shape, dir)),
             shape,
         }
     }
+    pub fn orientation(&self, dir: Direction) -> &OrientedShape {
+        &self.orientations[dir.rotation_count()]
+    }
 }
 
 impl Default for Card {
     fn default() -> Self {
-        Self {
-            id: 1,
-            name: String::from("ヒーローシューター"),
-            cost: 5,
-            power: 12, // shapeと対応を取ること
-            shape: CardShape::new("yyyyy\nyyyYy\n.y...\ny...."),
-        }
+        Self::new(
+            1,
+            "ヒーローシューター",
+            5,
+            CardShape::new("yyyyy\nyyyYy\n.y...\ny....").expect("正しい形式"),
+        )
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Copy, PartialOrd)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Hash)]
 pub enum Direction {
     Up,
     Down,
     Right,
     Left,
 }
+impl Direction {
+    /// 右回りの順に並べた全ての向き
+    pub const ALL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Right,
+        Direction::Down,
+        Direction::Left,
+    ];
+    // 上向きから右90度回転する回数
+    fn rotation_count(&self) -> usize {
+        match self {
+            Direction::Up => 0,
+            Direction::Right => 1,
+            Direction::Down => 2,
+            Direction::Left => 3,
+        }
+    }
+}
 impl std::fmt::Display for Direction {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         let s = match self {
@@ -376,17 +496,15 @@ impl std::fmt::Display for Direction {
         write!(f, "{}", s)
     }
 }
-impl From<char> for Direction {
-    fn from(c: char) -> Self {
-        match c {
-            'U' => Self::Up,
-            'D' => Self::Down,
-            'R' => Self::Right,
-            'L' => Self::Left,
-            _ => {
-                error!("Direction from error by: {}", c);
-                unreachable!()
-            }
+impl FromStr for Direction {
+    type Err = GameError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "U" => Ok(Self::Up),
+            "D" => Ok(Self::Down),
+            "R" => Ok(Self::Right),
+            "L" => Ok(Self::Left),
+            _ => Err(GameError::MalformedText(s.to_string())),
         }
     }
 }
@@ -405,16 +523,31 @@ impl std::fmt::Display for MulliganAction {
         write!(f, "{}", s)
     }
 }
-impl From<&str> for MulliganAction {
-    fn from(s: &str) -> Self {
+impl FromStr for MulliganAction {
+    type Err = GameError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.trim() {
-            "PASS" => Self::Pass,
-            "MULLIGAN" => Self::Mulligan,
-            _ => unreachable!(),
+            "PASS" => Ok(Self::Pass),
+            "MULLIGAN" => Ok(Self::Mulligan),
+            _ => Err(GameError::MalformedText(s.to_string())),
         }
     }
 }
 
+// 棋譜ではテキストのプロトコルと同じ文字列で表す
+impl Serialize for MulliganAction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for MulliganAction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy, PartialOrd)]
 pub enum Action {
     Pass {
@@ -464,60 +597,211 @@ impl std::fmt::Display for Action {
         write!(f, "{}", s)
     }
 }
-impl From<&str> for Action {
-    fn from(s: &str) -> Self {
+impl FromStr for Action {
+    type Err = GameError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || GameError::MalformedText(s.to_string());
         let chunks = s
             .trim()
             .split(' ')
             .filter(|s| !s.is_empty())
             .collect::<Vec<&str>>();
-        match chunks[0] {
-            "PASS" => Self::Pass {
-                card_id: chunks[1].parse::<usize>().unwrap(),
-            },
-            "PUT" => Self::Put {
-                card_id: chunks[1].parse::<usize>().unwrap(),
-                dir: Direction::from(chunks[2].parse::<char>().unwrap()),
-                y: chunks[3].parse::<usize>().unwrap(),
-                x: chunks[4].parse::<usize>().unwrap(),
-            },
-            "SPECIAL_PUT" => Self::SpecialPut {
-                card_id: chunks[1].parse::<usize>().unwrap(),
-                dir: Direction::from(chunks[2].parse::<char>().unwrap()),
-                y: chunks[3].parse::<usize>().unwrap(),
-                x: chunks[4].parse::<usize>().unwrap(),
-            },
-            _ => unreachable!(),
+        let parse_usize = |i: usize| -> Result<usize, GameError> {
+            chunks
+                .get(i)
+                .and_then(|v| v.parse::<usize>().ok())
+                .ok_or_else(malformed)
+        };
+        let parse_dir = |i: usize| -> Result<Direction, GameError> {
+            chunks.get(i).ok_or_else(malformed)?.parse::<Direction>()
+        };
+        match chunks.first() {
+            Some(&"PASS") if chunks.len() == 2 => Ok(Self::Pass {
+                card_id: parse_usize(1)?,
+            }),
+            Some(&"PUT") if chunks.len() == 5 => Ok(Self::Put {
+                card_id: parse_usize(1)?,
+                dir: parse_dir(2)?,
+                y: parse_usize(3)?,
+                x: parse_usize(4)?,
+            }),
+            Some(&"SPECIAL_PUT") if chunks.len() == 5 => Ok(Self::SpecialPut {
+                card_id: parse_usize(1)?,
+                dir: parse_dir(2)?,
+                y: parse_usize(3)?,
+                x: parse_usize(4)?,
+            }),
+            _ => Err(malformed()),
         }
     }
 }
 
-fn get_cursor(
-    reference_point_y: usize,
-    reference_point_x: usize,
-    target_y: usize,
-    target_x: usize,
-    i: usize,
-    j: usize,
-) -> Option<(usize, usize)> {
-    // 基準点(ry,rx)が(ty,tx)に置かれるので左下の座標がマイナスになる可能性がある
-    if target_y + i < reference_point_y || target_x + j < reference_point_x {
-        return None;
-    }
-    let cy = target_y + i - reference_point_y;
-    let cx = target_x + j - reference_point_x;
+// 棋譜ではテキストのプロトコルと同じ文字列で表す
+impl Serialize for Action {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for Action {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn get_cursor(target_y: usize, target_x: usize, dy: isize, dx: isize) -> Option<(usize, usize)> {
+    // 基準点が(ty,tx)に置かれるので左上の座標がマイナスになる可能性がある
+    let cy = target_y.checked_add_signed(dy)?;
+    let cx = target_x.checked_add_signed(dx)?;
     Some((cy, cx))
 }
 
+#[derive(Eq, PartialEq, Debug, Clone)]
 pub struct PlayerState {
     pub special_point: usize,
     pub hands: Vec<CardId>,
     pub deck: VecDeque<CardId>,
+    /// PUTでカードのマスが乗りうる、自分のマスに接した空白マス
+    pub anchors: BTreeSet<(usize, usize)>,
+    /// SPECIAL_PUTでカードのマスが乗りうる、自分のスペシャルマスに接したマス
+    pub special_anchors: BTreeSet<(usize, usize)>,
+    /// これまでのターンに出したアクション。全員に公開される。
+    pub played: Vec<Action>,
 }
+#[derive(Eq, PartialEq, Debug, Clone)]
 pub struct State {
     pub turn: usize,
     pub field: FieldShape,
     pub players: Vec<PlayerState>,
+    // applyで差分更新するZobristハッシュ。fieldなどを直接書き換えると整合しなくなる。
+    hash: u64,
+}
+
+/// フィールドに参加するすべてのプレイヤーのスペシャルマスがあり、参加しないプレイヤーのマスが無いことを検査する。
+pub fn check_field(env: &Environment, field: &Field) -> Result<(), GameError> {
+    // 参加していないプレイヤーのマスがあるフィールドでは遊べない
+    for row in field.shape.squares.iter() {
+        for square in row.iter() {
+            if let FieldSquareType::Colored { player_id }
+            | FieldSquareType::Special {
+                player_id,
+                activeted: _,
+            } = square
+            {
+                if env.player_size <= *player_id {
+                    return Err(GameError::InvalidField(format!(
+                        "field has squares of player {} but only {} players join",
+                        player_id, env.player_size
+                    )));
+                }
+            }
+        }
+    }
+    // スペシャルマスが無いプレイヤーはカードを置けない
+    let start_players = catalog::start_players(&field.shape);
+    if let Some(player_id) = (0..env.player_size).find(|p| !start_players.contains(p)) {
+        return Err(GameError::InvalidField(format!(
+            "player {} has no start special square",
+            player_id
+        )));
+    }
+    Ok(())
+}
+// デッキの枚数と、カード情報の存在するカードのみであることを検査する。
+fn check_deck(
+    env: &Environment,
+    cards: &HashMap<CardId, &Card>,
+    player_id: PlayerId,
+    deck: &[CardId],
+) -> Result<(), GameError> {
+    if deck.len() != env.deck_size {
+        return Err(GameError::InvalidDeckSize {
+            player_id,
+            expected: env.deck_size,
+            actual: deck.len(),
+        });
+    }
+    for card_id in deck.iter() {
+        if !cards.contains_key(card_id) {
+            return Err(GameError::UnknownCard {
+                player_id,
+                card_id: *card_id,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// `State::apply_with_undo`で変化する前の状態。`State::undo`で元に戻す。
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct UndoRecord {
+    turn: usize,
+    hash: u64,
+    squares: Vec<((usize, usize), FieldSquareType)>,
+    players: Vec<PlayerUndo>,
+}
+/// `State::apply`で1ターンの間に起きたこと。マスの座標は(y, x)で、行優先の順に並ぶ。
+#[derive(Eq, PartialEq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TurnOutcome {
+    /// 処理したターン
+    pub turn: usize,
+    /// プレイヤーごとの、このターンに塗って自分のマスになったマス
+    pub painted: Vec<Vec<(usize, usize)>>,
+    /// 同じパワーのカードの衝突でブロックマスになったマス
+    pub blocked: Vec<(usize, usize)>,
+    /// 前のターンまでに誰かのマスだったところを上塗りしたマスと、上塗りされる前の持ち主
+    pub overpainted: Vec<((usize, usize), PlayerId)>,
+    /// プレイヤーごとの、このターンに活性化したスペシャルマス
+    pub activated: Vec<Vec<(usize, usize)>>,
+    /// プレイヤーごとの、パスで得たスペシャルポイント
+    pub special_points_from_pass: Vec<usize>,
+    /// プレイヤーごとの、スペシャルマスの活性化で得たスペシャルポイント
+    pub special_points_from_activation: Vec<usize>,
+    /// プレイヤーごとの、スペシャルアタックで使ったスペシャルポイント
+    pub special_points_spent: Vec<usize>,
+}
+impl TurnOutcome {
+    fn new(turn: usize, player_size: usize) -> Self {
+        Self {
+            turn,
+            painted: vec![vec![]; player_size],
+            activated: vec![vec![]; player_size],
+            special_points_from_pass: vec![0; player_size],
+            special_points_from_activation: vec![0; player_size],
+            special_points_spent: vec![0; player_size],
+            ..Self::default()
+        }
+    }
+}
+impl Display for TurnOutcome {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let per_player = |values: Vec<usize>| {
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+        write!(
+            f,
+            "turn:{} painted:[{}] blocked:{} overpainted:{} activated:[{}] pass:[{}] activation:[{}] spent:[{}]",
+            self.turn,
+            per_player(self.painted.iter().map(|s| s.len()).collect()),
+            self.blocked.len(),
+            self.overpainted.len(),
+            per_player(self.activated.iter().map(|s| s.len()).collect()),
+            per_player(self.special_points_from_pass.clone()),
+            per_player(self.special_points_from_activation.clone()),
+            per_player(self.special_points_spent.clone()),
+        )
+    }
+}
+#[derive(Eq, PartialEq, Debug, Clone)]
+struct PlayerUndo {
+    special_point: usize,
+    hands: Vec<CardId>,
+    drawn_card_id: Option<CardId>,
 }
 impl State {
     pub fn new(
@@ -525,13 +809,16 @@ impl State {
         cards: &HashMap<CardId, &Card>,
         field: &Field,
         decks: &[Vec<CardId>],
-    ) -> Self {
-        for deck in decks.iter() {
-            assert_eq!(deck.len(), env.deck_size);
-            // デッキに含まれるのはカード情報の存在するカードのみであることを検査する。
-            for card_id in deck.iter() {
-                assert!(cards.contains_key(card_id));
-            }
+    ) -> Result<Self, GameError> {
+        if decks.len() != env.player_size {
+            return Err(GameError::PlayerCountMismatch {
+                expected: env.player_size,
+                actual: decks.len(),
+            });
+        }
+        check_field(env, field)?;
+        for (player_id, deck) in decks.iter().enumerate() {
+            check_deck(env, cards, player_id, deck)?;
         }
 
         let mut cloned_decks = decks
@@ -540,40 +827,76 @@ impl State {
             .collect::<Vec<VecDeque<CardId>>>();
 
         let mut hands = vec![];
-        for player_id in 0..env.player_size {
+        for deck in cloned_decks.iter_mut() {
             let mut hand: Vec<CardId> = vec![];
             for _ in 0..env.hand_size {
-                if let Some(card_id) = cloned_decks[player_id].pop_front() {
+                if let Some(card_id) = deck.pop_front() {
                     hand.push(card_id);
                 }
             }
             hands.push(hand);
         }
 
-        State {
-            turn: 1,
-            field: field.shape.clone(),
-            players: hands
+        Ok(State::from_parts(
+            1,
+            field.shape.clone(),
+            hands
                 .into_iter()
-                .zip(cloned_decks.into_iter())
+                .zip(cloned_decks)
                 .map(|(hand, deck)| PlayerState {
                     special_point: 0,
                     hands: hand,
                     deck,
+                    anchors: BTreeSet::new(),
+                    special_anchors: BTreeSet::new(),
+                    played: vec![],
                 })
                 .collect::<Vec<PlayerState>>(),
+        ))
+    }
+    // アンカーとハッシュを計算し直して局面を作る。playersのアンカーは無視される。
+    fn from_parts(turn: usize, field: FieldShape, players: Vec<PlayerState>) -> Self {
+        let mut state = State {
+            turn,
+            field,
+            players,
+            hash: 0,
+        };
+        for player in state.players.iter_mut() {
+            player.anchors.clear();
+            player.special_anchors.clear();
         }
+        let all_squares = (0..state.field.height)
+            .flat_map(|y| (0..state.field.width).map(move |x| (y, x)))
+            .collect::<Vec<(usize, usize)>>();
+        state.update_anchors(&all_squares);
+        state.hash = state.compute_zobrist_hash();
+        state
+    }
+    /// プレイヤーごとのマス数
+    pub fn scores(&self) -> Vec<usize> {
+        (0..self.players.len())
+            .map(|player_id| self.field.count_player(player_id))
+            .collect()
+    }
+    /// プレイヤーごとの順位（1位が1）。同じ順位のプレイヤーが複数いることもある。
+    pub fn ranking(&self, env: &Environment) -> Vec<usize> {
+        env.rule_set().ranking(self)
     }
+    /// 単独1位で終了したか
     pub fn is_win(&self, env: &Environment, player_id: PlayerId) -> bool {
+        let ranking = self.ranking(env);
         self.is_done(env)
-            && self.field.count_player(player_id) > self.field.count_player(1 - player_id)
+            && ranking[player_id] == 1
+            && ranking.iter().filter(|&&rank| rank == 1).count() == 1
     }
+    /// 自分よりマス数の多いプレイヤーがいる状態で終了したか
     pub fn is_lose(&self, env: &Environment, player_id: PlayerId) -> bool {
-        self.is_done(env)
-            && self.field.count_player(player_id) < self.field.count_player(1 - player_id)
+        self.is_done(env) && self.ranking(env)[player_id] > 1
     }
+    /// 1位が複数人いる状態で終了したか
     pub fn is_draw(&self, env: &Environment) -> bool {
-        self.is_done(env) && self.field.count_player(0) == self.field.count_player(1)
+        self.is_done(env) && self.ranking(env).iter().filter(|&&rank| rank == 1).count() > 1
     }
     pub fn is_done(&self, env: &Environment) -> bool {
         self.turn > env.max_turn
@@ -584,147 +907,282 @@ impl State {
         action: &Action,
         player_id: usize,
     ) -> bool {
+        self.check_action(cards, action, player_id).is_ok()
+    }
+    /// アクションが現在の盤面で合法かを検証し、不正な場合はその理由を返す。
+    pub fn check_action(
+        &self,
+        cards: &HashMap<CardId, &Card>,
+        action: &Action,
+        player_id: PlayerId,
+    ) -> Result<(), GameError> {
         assert!(player_id < self.players.len());
+        let card_id = action.get_card_id();
+        if !self.players[player_id].hands.contains(&card_id) {
+            return Err(GameError::CardNotInHand { player_id, card_id });
+        }
+        let card = cards
+            .get(&card_id)
+            .ok_or(GameError::UnknownCard { player_id, card_id })?;
         match action {
-            Action::Pass { card_id } => self.players[player_id].hands.contains(card_id),
-            Action::Put { card_id, dir, y, x } => {
-                let card = cards
-                    .get(card_id)
-                    .expect("all cards in deck are contained cards");
-                let shape = match dir {
-                    Direction::Up => card.shape.clone(),
-                    Direction::Right => card.shape.rotate(),
-                    Direction::Down => card.shape.rotate().rotate(),
-                    Direction::Left => card.shape.rotate().rotate().rotate(),
-                };
+            Action::Pass { card_id: _ } => Ok(()),
+            Action::Put {
+                card_id: _,
+                dir,
+                y,
+                x,
+            } => {
                 // 1. 既存の自分のマスに接しているか
                 // 2. 全ての追加するマスがフィールド内で空白マスに重なる
                 let mut is_adjacent = false;
-                let (ry, rx) = shape.find_reference_point(0);
-                for i in 0..card.shape.height {
-                    for j in 0..card.shape.width {
-                        if matches!(
-                            card.shape.squares[i][j],
-                            CardSquareType::Colored | CardSquareType::Special
-                        ) {
-                            // 基準点(ry,rx)が(y,x)に置かれるので左下の座標がマイナスになる可能性がある
-                            let cur = get_cursor(ry, rx, *y, *x, i, j);
-                            if cur.is_none() {
-                                return false;
-                            }
-                            let (cy, cx) = cur.expect("noneはアーリーリターンしている。");
-                            if self.field.height <= cy || self.field.width <= cx {
-                                return false;
-                            }
+                for &(dy, dx, _) in card.orientation(*dir).squares.iter() {
+                    // 基準点が(y,x)に置かれるので左上の座標がマイナスになる可能性がある
+                    let (cy, cx) = get_cursor(*y, *x, dy, dx)
+                        .ok_or(GameError::OutOfField { player_id, card_id })?;
+                    if self.field.height <= cy || self.field.width <= cx {
+                        return Err(GameError::OutOfField { player_id, card_id });
+                    }
 
-                            if self.field.squares[cy][cx] != FieldSquareType::Empty {
-                                return false;
-                            }
-                            // 既存の自身のマスに隣接しているか判定する
-                            for (dy, dx) in DYDX8.iter() {
-                                if is_adjacent {
-                                    break;
-                                }
-                                if self.field.height <= cy.wrapping_add(*dy)
-                                    || self.field.width <= cx.wrapping_add(*dx)
-                                {
-                                    continue;
-                                }
-                                if let FieldSquareType::Colored { player_id: pid }
-                                | FieldSquareType::Special {
-                                    player_id: pid,
-                                    activeted: _,
-                                } =
-                                    self.field.squares[cy.wrapping_add(*dy)][cx.wrapping_add(*dx)]
-                                {
-                                    if pid == player_id {
-                                        is_adjacent = true;
-                                    }
-                                }
+                    if self.field.squares[cy][cx] != FieldSquareType::Empty {
+                        return Err(GameError::Overlap {
+                            player_id,
+                            card_id,
+                            y: cy,
+                            x: cx,
+                        });
+                    }
+                    // 既存の自身のマスに隣接しているか判定する
+                    for (dy, dx) in DYDX8.iter() {
+                        if is_adjacent {
+                            break;
+                        }
+                        if self.field.height <= cy.wrapping_add(*dy)
+                            || self.field.width <= cx.wrapping_add(*dx)
+                        {
+                            continue;
+                        }
+                        if let FieldSquareType::Colored { player_id: pid }
+                        | FieldSquareType::Special {
+                            player_id: pid,
+                            activeted: _,
+                        } = self.field.squares[cy.wrapping_add(*dy)][cx.wrapping_add(*dx)]
+                        {
+                            if pid == player_id {
+                                is_adjacent = true;
                             }
                         }
                     }
                 }
-                is_adjacent
+                if is_adjacent {
+                    Ok(())
+                } else {
+                    Err(GameError::NotAdjacent { player_id, card_id })
+                }
             }
-            Action::SpecialPut { card_id, dir, y, x } => {
-                let card = cards
-                    .get(card_id)
-                    .expect("all cards in deck are contained cards");
+            Action::SpecialPut {
+                card_id: _,
+                dir,
+                y,
+                x,
+            } => {
                 // スペシャルショットのコストが払えるのか？
                 if self.players[player_id].special_point < card.cost {
-                    return false;
+                    return Err(GameError::NotEnoughSpecialPoints {
+                        player_id,
+                        card_id,
+                        required: card.cost,
+                        actual: self.players[player_id].special_point,
+                    });
                 }
 
-                let shape = match dir {
-                    Direction::Up => card.shape.clone(),
-                    Direction::Right => card.shape.rotate(),
-                    Direction::Down => card.shape.rotate().rotate(),
-                    Direction::Left => card.shape.rotate().rotate().rotate(),
-                };
                 // 1. 既存の自分のスペシャルマスに接しているか
                 // 2. 全ての追加するマスがフィールド内でスペシャルマス・ブロックマス以外（空白マス・自マス・相手マス）に重なる
                 let mut is_adjacent = false;
-                let (ry, rx) = shape.find_reference_point(0);
-                for i in 0..card.shape.height {
-                    for j in 0..card.shape.width {
-                        if matches!(
-                            card.shape.squares[i][j],
-                            CardSquareType::Colored | CardSquareType::Special
-                        ) {
-                            // 枠内＆SpecialとBlock以外＆Specialに隣接
-                            // 基準点(ry,rx)が(y,x)に置かれるので左下の座標がマイナスになる可能性がある
-                            let cur = get_cursor(ry, rx, *y, *x, i, j);
-                            if cur.is_none() {
-                                return false;
-                            }
-                            let (cy, cx) = cur.expect("noneはアーリーリターンしている。");
-                            if self.field.height <= cy || self.field.width <= cx {
-                                return false;
-                            }
+                for &(dy, dx, _) in card.orientation(*dir).squares.iter() {
+                    // 枠内＆SpecialとBlock以外＆Specialに隣接
+                    // 基準点が(y,x)に置かれるので左上の座標がマイナスになる可能性がある
+                    let (cy, cx) = get_cursor(*y, *x, dy, dx)
+                        .ok_or(GameError::OutOfField { player_id, card_id })?;
+                    if self.field.height <= cy || self.field.width <= cx {
+                        return Err(GameError::OutOfField { player_id, card_id });
+                    }
 
-                            if matches!(
-                                self.field.squares[cy][cx],
-                                FieldSquareType::Special {
-                                    player_id: _,
-                                    activeted: _
-                                } | FieldSquareType::Block
-                            ) {
-                                return false;
-                            }
+                    if matches!(
+                        self.field.squares[cy][cx],
+                        FieldSquareType::Special {
+                            player_id: _,
+                            activeted: _
+                        } | FieldSquareType::Block
+                    ) {
+                        return Err(GameError::Overlap {
+                            player_id,
+                            card_id,
+                            y: cy,
+                            x: cx,
+                        });
+                    }
 
-                            // 既存の自身のスペシャルマスに隣接しているか判定する
-                            for (dy, dx) in DYDX8.iter() {
-                                if is_adjacent {
-                                    break;
-                                }
-                                if self.field.height <= cy.wrapping_add(*dy)
-                                    || self.field.width <= cx.wrapping_add(*dx)
-                                {
-                                    continue;
-                                }
-                                if let FieldSquareType::Special {
-                                    player_id: pid,
-                                    activeted: _,
-                                } =
-                                    self.field.squares[cy.wrapping_add(*dy)][cx.wrapping_add(*dx)]
-                                {
-                                    if pid == player_id {
-                                        is_adjacent = true;
-                                    }
-                                }
+                    // 既存の自身のスペシャルマスに隣接しているか判定する
+                    for (dy, dx) in DYDX8.iter() {
+                        if is_adjacent {
+                            break;
+                        }
+                        if self.field.height <= cy.wrapping_add(*dy)
+                            || self.field.width <= cx.wrapping_add(*dx)
+                        {
+                            continue;
+                        }
+                        if let FieldSquareType::Special {
+                            player_id: pid,
+                            activeted: _,
+                        } = self.field.squares[cy.wrapping_add(*dy)][cx.wrapping_add(*dx)]
+                        {
+                            if pid == player_id {
+                                is_adjacent = true;
                             }
                         }
                     }
                 }
-                is_adjacent
+                if is_adjacent {
+                    Ok(())
+                } else {
+                    Err(GameError::NotAdjacent { player_id, card_id })
+                }
             }
         }
     }
+    /// 自分のマス（スペシャルマス）に接したアンカーの周りだけを調べて合法手を列挙する。
     pub fn generate_valid_actions(
         &mut self,
         cards: &HashMap<CardId, &Card>,
         player_id: PlayerId,
+    ) -> Vec<Action> {
+        let board = match Bitboard::new(&self.field, self.players.len()) {
+            Some(board) => board,
+            None => return self.generate_valid_actions_naive(cards, player_id),
+        };
+        let player = &self.players[player_id];
+        let anchors = bitboard::to_rows(&player.anchors);
+        let special_anchors = bitboard::to_rows(&player.special_anchors);
+        let mut candidates = vec![];
+        for card_id in player.hands.iter() {
+            let card = cards
+                .get(card_id)
+                .expect("all cards in deck are contained cards");
+            for dir in Direction::ALL.iter() {
+                let orientation = card.orientation(*dir);
+                let mask = &orientation.mask;
+                let (ry, rx) = orientation.reference_point;
+                for (oy, ox) in Self::top_lefts(&board, mask, &anchors) {
+                    if board.can_put(mask, player_id, oy, ox) {
+                        candidates.push(Action::Put {
+                            card_id: *card_id,
+                            dir: *dir,
+                            y: oy + ry,
+                            x: ox + rx,
+                        });
+                    }
+                }
+                if card.cost <= player.special_point {
+                    for (oy, ox) in Self::top_lefts(&board, mask, &special_anchors) {
+                        if board.can_special_put(mask, player_id, oy, ox) {
+                            candidates.push(Action::SpecialPut {
+                                card_id: *card_id,
+                                dir: *dir,
+                                y: oy + ry,
+                                x: ox + rx,
+                            });
+                        }
+                    }
+                }
+            }
+            candidates.push(Action::Pass { card_id: *card_id });
+        }
+        candidates
+    }
+    // いずれかのマスがアンカーに乗り、フィールドに収まる左上の座標を総当たりと同じ順に列挙する
+    fn top_lefts(
+        board: &Bitboard,
+        mask: &CardMask,
+        anchors: &bitboard::Rows,
+    ) -> Vec<(usize, usize)> {
+        let mut top_lefts = vec![];
+        if board.height < mask.height || board.width < mask.width {
+            return top_lefts;
+        }
+        let positions = mask.positions_touching(anchors);
+        let columns = (1u64 << (board.width - mask.width + 1)) - 1;
+        for (oy, &row) in positions
+            .iter()
+            .enumerate()
+            .take(board.height - mask.height + 1)
+        {
+            let mut bits = row & columns;
+            while bits != 0 {
+                top_lefts.push((oy, bits.trailing_zeros() as usize));
+                bits &= bits - 1;
+            }
+        }
+        top_lefts
+    }
+    // (y, x)とその8近傍のうちフィールド内のマス
+    fn squares_around(&self, y: usize, x: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        DYDX8
+            .iter()
+            .chain([(0, 0)].iter())
+            .map(move |(dy, dx)| (y.wrapping_add(*dy), x.wrapping_add(*dx)))
+            .filter(|&(ny, nx)| ny < self.field.height && nx < self.field.width)
+    }
+    fn update_anchors(&mut self, squares: &[(usize, usize)]) {
+        for (player_id, player) in self.players.iter_mut().enumerate() {
+            for &(y, x) in squares.iter() {
+                let mut is_next_to_own = false;
+                let mut is_next_to_own_special = false;
+                for (dy, dx) in DYDX8.iter() {
+                    let ny = y.wrapping_add(*dy);
+                    let nx = x.wrapping_add(*dx);
+                    if self.field.height <= ny || self.field.width <= nx {
+                        continue;
+                    }
+                    match self.field.squares[ny][nx] {
+                        FieldSquareType::Colored { player_id: pid } if pid == player_id => {
+                            is_next_to_own = true;
+                        }
+                        FieldSquareType::Special {
+                            player_id: pid,
+                            activeted: _,
+                        } if pid == player_id => {
+                            is_next_to_own = true;
+                            is_next_to_own_special = true;
+                        }
+                        _ => {}
+                    }
+                }
+                let square = self.field.squares[y][x];
+                if is_next_to_own && square == FieldSquareType::Empty {
+                    player.anchors.insert((y, x));
+                } else {
+                    player.anchors.remove(&(y, x));
+                }
+                if is_next_to_own_special
+                    && !matches!(
+                        square,
+                        FieldSquareType::Special { .. } | FieldSquareType::Block
+                    )
+                {
+                    player.special_anchors.insert((y, x));
+                } else {
+                    player.special_anchors.remove(&(y, x));
+                }
+            }
+        }
+    }
+    /// 全てのマスについて`check_action`で判定して合法手を列挙する。
+    /// `generate_valid_actions`と同じ結果になるので、検証や比較に使う。
+    pub fn generate_valid_actions_naive(
+        &self,
+        cards: &HashMap<CardId, &Card>,
+        player_id: PlayerId,
     ) -> Vec<Action> {
         let mut candidates = vec![];
         for card_id in self.players[player_id].hands.iter() {
@@ -732,14 +1190,7 @@ impl State {
                 .get(card_id)
                 .expect("all cards in deck are contained cards")
                 .cost;
-            for dir in [
-                Direction::Up,
-                Direction::Right,
-                Direction::Down,
-                Direction::Left,
-            ]
-            .iter()
-            {
+            for dir in Direction::ALL.iter() {
                 for y in 0..self.field.height {
                     for x in 0..self.field.width {
                         let action = Action::Put {
@@ -774,8 +1225,49 @@ impl State {
         candidates
     }
 
-    fn activates(&mut self, putted_this_turn_squares: &[(usize, usize)]) -> Vec<usize> {
-        let mut activated_counts = vec![0; self.players.len()];
+    // パワーの大きい順に呼ばれる前提で、カードの1マスを塗る。
+    // 同じターンに塗られたマスと重なった場合の扱いはルールに従う。
+    fn paint(
+        &mut self,
+        rules: &dyn RuleSet,
+        unfixed_squares: &mut HashMap<(usize, usize), Paint>,
+        (y, x): (usize, usize),
+        paint: Paint,
+    ) {
+        let painted = match paint.square_type {
+            CardSquareType::Special => FieldSquareType::Special {
+                player_id: paint.player_id,
+                activeted: false,
+            },
+            _ => FieldSquareType::Colored {
+                player_id: paint.player_id,
+            },
+        };
+        match unfixed_squares.get(&(y, x)) {
+            // 過去ターンのマス。置けることはis_valid_actionで確認済み
+            None => {
+                unfixed_squares.insert((y, x), paint);
+                self.field.squares[y][x] = painted;
+            }
+            Some(unfixed) => match rules.resolve_overlap(unfixed, &paint) {
+                Overlap::Overwrite => {
+                    unfixed_squares.insert((y, x), paint);
+                    self.field.squares[y][x] = painted;
+                }
+                Overlap::Keep => {}
+                // 後から同じパワーのカードが重なってもブロックになるよう、先に塗ったマスの情報を残す
+                Overlap::Block => self.field.squares[y][x] = FieldSquareType::Block,
+            },
+        }
+    }
+
+    // 塗ったマスの周囲のスペシャルマスを活性化し、プレイヤーごとに活性化したマスを返す
+    fn activates(
+        &mut self,
+        rules: &dyn RuleSet,
+        putted_this_turn_squares: &[(usize, usize)],
+    ) -> Vec<Vec<(usize, usize)>> {
+        let mut activated_squares = vec![vec![]; self.players.len()];
         for (y, x) in putted_this_turn_squares {
             for (dy, dx) in DYDX8.iter() {
                 let ny = y.wrapping_add(*dy);
@@ -788,42 +1280,156 @@ impl State {
                     activeted: false,
                 } = self.field.squares[ny][nx]
                 {
-                    let special_y = ny;
-                    let special_x = nx;
-                    let mut has_neighbor_empty = false;
-                    for (dy, dx) in DYDX8.iter() {
-                        if self.field.height <= special_y.wrapping_add(*dy)
-                            || self.field.width <= special_x.wrapping_add(*dx)
-                        {
-                            continue;
-                        }
-                        if matches!(
-                            self.field.squares[special_y.wrapping_add(*dy)]
-                                [special_x.wrapping_add(*dx)],
-                            FieldSquareType::Empty
-                        ) {
-                            has_neighbor_empty = true;
-                            break;
-                        }
+                    if rules.is_activated(&self.field, ny, nx) {
+                        self.players[player_id].special_point +=
+                            rules.special_points_for_activation();
+                        activated_squares[player_id].push((ny, nx));
+                        self.field.squares[ny][nx].activate();
+                    } else if rules.checks_activation_once() {
+                        // ポイントは得ずに活性化済みにする
+                        self.field.squares[ny][nx].activate();
                     }
-                    if !has_neighbor_empty {
-                        self.players[player_id].special_point += 1;
-                        activated_counts[player_id] += 1;
-                    }
-                    self.field.squares[special_y][special_x].activate();
                 }
             }
         }
-        activated_counts
+        for squares in activated_squares.iter_mut() {
+            squares.sort();
+        }
+        activated_squares
     }
 
-    pub fn apply(&mut self, env: &Environment, cards: &HashMap<CardId, &Card>, actions: &[Action]) {
-        // まず受け取ったアクションが有効なことを確認する。
-        if !self.is_valid_action(cards, &actions[0], 0)
-            || !self.is_valid_action(cards, &actions[1], 1)
-        {
-            todo!("invalidな時の処理は後で書く");
+    // actionsでカードが置かれるマスとその周囲（スペシャルマスの活性化）。applyではこれらのマスだけが変化する。
+    fn touched_squares(
+        &self,
+        cards: &HashMap<CardId, &Card>,
+        actions: &[Action],
+    ) -> BTreeSet<(usize, usize)> {
+        let mut touched_squares = BTreeSet::new();
+        for action in actions.iter() {
+            let (card_id, dir, y, x) = match *action {
+                Action::Pass { card_id: _ } => continue,
+                Action::Put { card_id, dir, y, x } | Action::SpecialPut { card_id, dir, y, x } => {
+                    (card_id, dir, y, x)
+                }
+            };
+            let Some(card) = cards.get(&card_id) else {
+                continue;
+            };
+            for &(dy, dx, _) in card.orientation(dir).squares.iter() {
+                if let Some((cy, cx)) = get_cursor(y, x, dy, dx) {
+                    touched_squares.extend(self.squares_around(cy, cx));
+                }
+            }
         }
+        touched_squares
+    }
+    /// 局面のZobristハッシュ。`apply`と`undo`で差分更新される。
+    pub fn zobrist_hash(&self) -> u64 {
+        self.hash
+    }
+    /// 局面全体からZobristハッシュを計算し直す。
+    pub fn compute_zobrist_hash(&self) -> u64 {
+        let all_squares = (0..self.field.height)
+            .flat_map(|y| (0..self.field.width).map(move |x| (y, x)))
+            .collect::<Vec<(usize, usize)>>();
+        self.partial_zobrist_hash(&all_squares)
+    }
+    // 指定したマスと、マス以外の要素（ターン・スペシャルポイント・手札）のハッシュ
+    fn partial_zobrist_hash<'a>(
+        &self,
+        squares: impl IntoIterator<Item = &'a (usize, usize)>,
+    ) -> u64 {
+        let mut hash = zobrist::turn_key(self.turn);
+        for &(y, x) in squares {
+            hash ^= zobrist::square_key(y, x, self.field.squares[y][x]);
+        }
+        for (player_id, player) in self.players.iter().enumerate() {
+            hash ^= zobrist::special_point_key(player_id, player.special_point);
+            hash ^= zobrist::hand_key(player_id, &player.hands);
+        }
+        hash
+    }
+    /// `apply`と同じように進め、`undo`で元に戻すための記録を返す。
+    /// 探索で`State`を複製せずに手を試すために使う。
+    pub fn apply_with_undo(
+        &mut self,
+        env: &Environment,
+        cards: &HashMap<CardId, &Card>,
+        actions: &[Action],
+    ) -> Result<UndoRecord, GameError> {
+        let touched_squares = self.touched_squares(cards, actions);
+        let mut record = UndoRecord {
+            turn: self.turn,
+            hash: self.hash,
+            squares: touched_squares
+                .into_iter()
+                .map(|(y, x)| ((y, x), self.field.squares[y][x]))
+                .collect(),
+            players: self
+                .players
+                .iter()
+                .map(|player| PlayerUndo {
+                    special_point: player.special_point,
+                    hands: player.hands.clone(),
+                    drawn_card_id: None,
+                })
+                .collect(),
+        };
+        let deck_sizes = self
+            .players
+            .iter()
+            .map(|player| player.deck.len())
+            .collect::<Vec<usize>>();
+        self.apply(env, cards, actions)?;
+        for (i, player) in self.players.iter().enumerate() {
+            if player.deck.len() < deck_sizes[i] {
+                record.players[i].drawn_card_id = player.hands.last().copied();
+            }
+        }
+        Ok(record)
+    }
+    /// `apply_with_undo`を呼ぶ前の状態に戻す。
+    pub fn undo(&mut self, record: UndoRecord) {
+        for &((y, x), square) in record.squares.iter() {
+            self.field.squares[y][x] = square;
+        }
+        for (player, undo) in self.players.iter_mut().zip(record.players) {
+            player.special_point = undo.special_point;
+            player.hands = undo.hands;
+            player.played.pop();
+            if let Some(card_id) = undo.drawn_card_id {
+                player.deck.push_front(card_id);
+            }
+        }
+        self.turn = record.turn;
+        self.hash = record.hash;
+        let squares = record
+            .squares
+            .iter()
+            .map(|&(position, _)| position)
+            .collect::<Vec<(usize, usize)>>();
+        self.update_anchors(&squares);
+    }
+
+    pub fn apply(
+        &mut self,
+        env: &Environment,
+        cards: &HashMap<CardId, &Card>,
+        actions: &[Action],
+    ) -> Result<TurnOutcome, GameError> {
+        // まず受け取ったアクションが有効なことを確認する。盤面を変更する前に全員分を検証する。
+        if actions.len() != self.players.len() {
+            return Err(GameError::PlayerCountMismatch {
+                expected: self.players.len(),
+                actual: actions.len(),
+            });
+        }
+        for (player_id, action) in actions.iter().enumerate() {
+            self.check_action(cards, action, player_id)?;
+        }
+        // 変化しうる部分のハッシュを取り除いておき、最後に変化後の値を加える
+        let touched_squares = self.touched_squares(cards, actions);
+        self.hash ^= self.partial_zobrist_hash(&touched_squares);
 
         let mut action_orders = vec![];
         for (i, action) in actions.iter().enumerate() {
@@ -859,179 +1465,51 @@ impl State {
         }
         action_orders.sort_by_key(|x| std::cmp::Reverse(*x)); // パワーの大きい順に行動する
 
-        // 同一powerのカードの衝突を考慮する。
-        let mut unfixed_squares = HashMap::<(usize, usize), usize>::new();
+        // 同一powerのカードの衝突を考慮する。このターンに塗られたマスごとに、塗ったカードの情報を持つ。
+        let rules = env.rule_set();
+        let mut outcome = TurnOutcome::new(self.turn, self.players.len());
+        let mut unfixed_squares = HashMap::<(usize, usize), Paint>::new();
+        // このターンに塗られる前のマス
+        let mut previous_squares = HashMap::<(usize, usize), FieldSquareType>::new();
         for (_, action_index) in action_orders.iter() {
             match actions[*action_index] {
                 Action::Pass { card_id: _ } => {
-                    self.players[*action_index].special_point += 1;
+                    let special_point = rules.special_points_for_pass();
+                    self.players[*action_index].special_point += special_point;
+                    outcome.special_points_from_pass[*action_index] += special_point;
                 }
-                Action::Put { card_id, dir, y, x } => {
-                    let card = cards
-                        .get(&card_id)
-                        .expect("all cards in deck are contained cards");
-                    let shape = match dir {
-                        Direction::Up => card.shape.clone(),
-                        Direction::Right => card.shape.rotate(),
-                        Direction::Down => card.shape.rotate().rotate(),
-                        Direction::Left => card.shape.rotate().rotate().rotate(),
-                    };
-                    let (ry, rx) = shape.find_reference_point(0);
-                    for i in 0..card.shape.height {
-                        for j in 0..card.shape.width {
-                            if matches!(card.shape.squares[i][j], CardSquareType::Colored) {
-                                let cur = get_cursor(ry, rx, y, x, i, j);
-                                let (cy, cx) =
-                                    cur.expect("全てのマスがvalidな座標に収まることを確認済み");
-                                match self.field.squares[cy][cx] {
-                                    FieldSquareType::Empty => {
-                                        unfixed_squares.insert((cy, cx), card.power);
-                                        self.field.squares[cy][cx] = FieldSquareType::Colored {
-                                            player_id: *action_index,
-                                        };
-                                    }
-                                    FieldSquareType::Colored { player_id: _ } => {
-                                        let unfixed_square_power = unfixed_squares.get(&(cy,cx)).expect("is_valid_actionをクリアしているなら同一ターンに置かれているはず");
-                                        self.field.squares[cy][cx] =
-                                            if *unfixed_square_power == card.power {
-                                                FieldSquareType::Block
-                                            } else {
-                                                unfixed_squares.insert((cy, cx), card.power);
-                                                FieldSquareType::Colored {
-                                                    player_id: *action_index,
-                                                }
-                                            };
-                                    }
-                                    _ => { /* それ以外には置けない */ }
-                                }
-                            } else if matches!(card.shape.squares[i][j], CardSquareType::Special) {
-                                let cur = get_cursor(ry, rx, y, x, i, j);
-                                let (cy, cx) =
-                                    cur.expect("全てのマスがvalidな座標に収まることを確認済み");
-                                match self.field.squares[cy][cx] {
-                                    FieldSquareType::Empty
-                                    | FieldSquareType::Colored { player_id: _ } => {
-                                        unfixed_squares.insert((cy, cx), card.power);
-                                        self.field.squares[cy][cx] = FieldSquareType::Special {
-                                            player_id: *action_index,
-                                            activeted: false,
-                                        };
-                                    }
-                                    FieldSquareType::Special {
-                                        player_id: _,
-                                        activeted: _,
-                                    } => {
-                                        let unfixed_square_power = unfixed_squares.get(&(cy,cx)).expect("is_valid_actionをクリアしているなら同一ターンに置かれているはず");
-                                        self.field.squares[cy][cx] =
-                                            if *unfixed_square_power == card.power {
-                                                FieldSquareType::Block
-                                            } else {
-                                                unfixed_squares.insert((cy, cx), card.power);
-                                                FieldSquareType::Special {
-                                                    player_id: *action_index,
-                                                    activeted: false,
-                                                }
-                                            };
-                                    }
-                                    _ => { /* それ以外には置けない */ }
-                                }
-                            }
-                        }
+                Action::Put { card_id, dir, y, x } | Action::SpecialPut { card_id, dir, y, x } => {
+                    if matches!(actions[*action_index], Action::SpecialPut { .. }) {
+                        debug!("use SpecialPut:{:?}", actions[*action_index]);
                     }
-                }
-                Action::SpecialPut { card_id, dir, y, x } => {
-                    debug!("use SpecialPut:{:?}", actions[*action_index]);
                     let card = cards
                         .get(&card_id)
                         .expect("all cards in deck are contained cards");
-                    let shape = match dir {
-                        Direction::Up => card.shape.clone(),
-                        Direction::Right => card.shape.rotate(),
-                        Direction::Down => card.shape.rotate().rotate(),
-                        Direction::Left => card.shape.rotate().rotate().rotate(),
-                    };
-                    let (ry, rx) = shape.find_reference_point(0);
-                    for i in 0..card.shape.height {
-                        for j in 0..card.shape.width {
-                            if matches!(card.shape.squares[i][j], CardSquareType::Colored) {
-                                let cur = get_cursor(ry, rx, y, x, i, j);
-                                let (cy, cx) =
-                                    cur.expect("全てのマスがvalidな座標に収まることを確認済み");
-                                match self.field.squares[cy][cx] {
-                                    FieldSquareType::Empty => {
-                                        unfixed_squares.insert((cy, cx), card.power);
-                                        self.field.squares[cy][cx] = FieldSquareType::Colored {
-                                            player_id: *action_index,
-                                        };
-                                    }
-                                    FieldSquareType::Colored { player_id: _ } => {
-                                        self.field.squares[cy][cx] = match unfixed_squares
-                                            .get(&(cy, cx))
-                                        {
-                                            Some(unfixed_square_power) => {
-                                                // 同ターン、同パワー
-                                                if *unfixed_square_power == card.power {
-                                                    FieldSquareType::Block
-                                                } else {
-                                                    // 同ターン、高パワー
-                                                    unfixed_squares.insert((cy, cx), card.power);
-                                                    FieldSquareType::Colored {
-                                                        player_id: *action_index,
-                                                    }
-                                                }
-                                            }
-                                            None => {
-                                                // 過去ターン
-                                                unfixed_squares.insert((cy, cx), card.power);
-                                                FieldSquareType::Colored {
-                                                    player_id: *action_index,
-                                                }
-                                            }
-                                        };
-                                    }
-                                    _ => { /* それ以外には置けない */ }
-                                }
-                            } else if matches!(card.shape.squares[i][j], CardSquareType::Special) {
-                                let cur = get_cursor(ry, rx, y, x, i, j);
-                                let (cy, cx) =
-                                    cur.expect("全てのマスがvalidな座標に収まることを確認済み");
-                                match self.field.squares[cy][cx] {
-                                    FieldSquareType::Empty
-                                    | FieldSquareType::Colored { player_id: _ } => {
-                                        unfixed_squares.insert((cy, cx), card.power);
-                                        self.field.squares[cy][cx] = FieldSquareType::Special {
-                                            player_id: *action_index,
-                                            activeted: false,
-                                        };
-                                    }
-                                    FieldSquareType::Special {
-                                        player_id: _,
-                                        activeted: _,
-                                    } => {
-                                        self.field.squares[cy][cx] = match unfixed_squares
-                                            .get(&(cy, cx))
-                                        {
-                                            Some(unfixed_square_power) => {
-                                                // 同ターン、同パワー
-                                                if *unfixed_square_power == card.power {
-                                                    FieldSquareType::Block
-                                                } else {
-                                                    // 同ターン、高パワー
-                                                    unfixed_squares.insert((cy, cx), card.power);
-                                                    FieldSquareType::Colored {
-                                                        player_id: *action_index,
-                                                    }
-                                                }
-                                            }
-                                            None => unreachable!(),
-                                        };
-                                    }
-                                    _ => { /* それ以外には置けない */ }
-                                }
-                            }
-                        }
+                    for &(dy, dx, square_type) in card.orientation(dir).squares.iter() {
+                        let (cy, cx) = get_cursor(y, x, dy, dx)
+                            .expect("全てのマスがvalidな座標に収まることを確認済み");
+                        previous_squares
+                            .entry((cy, cx))
+                            .or_insert(self.field.squares[cy][cx]);
+                        self.paint(
+                            rules,
+                            &mut unfixed_squares,
+                            (cy, cx),
+                            Paint {
+                                player_id: *action_index,
+                                power: card.power,
+                                square_type,
+                                special_attack: matches!(
+                                    actions[*action_index],
+                                    Action::SpecialPut { .. }
+                                ),
+                            },
+                        );
+                    }
+                    if matches!(actions[*action_index], Action::SpecialPut { .. }) {
+                        self.players[*action_index].special_point -= card.cost;
+                        outcome.special_points_spent[*action_index] += card.cost;
                     }
-                    self.players[*action_index].special_point -= card.cost;
                 }
             }
         }
@@ -1040,8 +1518,35 @@ impl State {
         let putted_this_turn_squares = unfixed_squares
             .keys()
             .cloned()
+            .collect::<BTreeSet<(usize, usize)>>()
+            .into_iter()
             .collect::<Vec<(usize, usize)>>();
-        self.activates(&putted_this_turn_squares);
+        outcome.activated = self.activates(rules, &putted_this_turn_squares);
+        for (player_id, squares) in outcome.activated.iter().enumerate() {
+            outcome.special_points_from_activation[player_id] =
+                squares.len() * rules.special_points_for_activation();
+        }
+        for &(y, x) in putted_this_turn_squares.iter() {
+            match self.field.squares[y][x] {
+                FieldSquareType::Colored { player_id }
+                | FieldSquareType::Special { player_id, .. } => {
+                    outcome.painted[player_id].push((y, x))
+                }
+                FieldSquareType::Block => outcome.blocked.push((y, x)),
+                FieldSquareType::Empty => unreachable!("塗ったマスは空白マスにならない"),
+            }
+            if let FieldSquareType::Colored { player_id }
+            | FieldSquareType::Special { player_id, .. } = previous_squares[&(y, x)]
+            {
+                outcome.overpainted.push(((y, x), player_id));
+            }
+        }
+        // 変化したマスとその周囲だけアンカーを更新する
+        let around_squares = putted_this_turn_squares
+            .iter()
+            .flat_map(|&(y, x)| self.squares_around(y, x))
+            .collect::<BTreeSet<(usize, usize)>>();
+        self.update_anchors(&around_squares.into_iter().collect::<Vec<(usize, usize)>>());
 
         // 使ったカードを捨てる
         for (i, &action) in actions.iter().enumerate() {
@@ -1056,12 +1561,13 @@ impl State {
             assert!(index <= self.players[i].hands.len()); // removeはpanicする可能性があるので検証しておく
             assert_eq!(self.players[i].hands[index], card_id);
             self.players[i].hands.remove(index);
+            self.players[i].played.push(action);
         }
 
         // 次のターン
         self.turn += 1;
         // 新しいカードを引く
-        if !self.is_done(env) {
+        if rules.draws_card(env, self) {
             for i in 0..self.players.len() {
                 let new_card_id = self.players[i]
                     .deck
@@ -1070,10 +1576,12 @@ impl State {
                 self.players[i].hands.push(new_card_id);
             }
         }
+        self.hash ^= self.partial_zobrist_hash(&touched_squares);
+        Ok(outcome)
     }
 }
 
-#[derive(Clone)]
+#[derive(Eq, PartialEq, Debug, Clone)]
 pub struct Field {
     pub id: FieldId,
     pub name: String,
@@ -1094,7 +1602,8 @@ impl Default for Field {
                         .repeat(3)
                         .strip_suffix('\n')
                         .unwrap()),
-            ),
+            )
+            .expect("正しい形式"),
         }
     }
 }
@@ -1102,6 +1611,55 @@ impl Default for Field {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+    use std::collections::HashSet;
+
+    fn sample_catalog() -> Vec<Card> {
+        catalog::parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap()
+    }
+    // ランダムに手を選んでゲームを進め、各ターンの盤面をcheckで検査する
+    fn play_random_games(
+        n_games: usize,
+        mut check: impl FnMut(&mut State, &HashMap<CardId, &Card>),
+    ) {
+        let env = Environment::new(2, 15, 4, 12, true);
+        let catalog = sample_catalog();
+        let cards = catalog
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<_, _>>();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..n_games {
+            let decks = (0..env.player_size)
+                .map(|_| {
+                    (0..env.deck_size)
+                        .map(|_| catalog.choose(&mut rng).unwrap().id)
+                        .collect::<Vec<CardId>>()
+                })
+                .collect::<Vec<_>>();
+            let mut state = State::new(&env, &cards, &Field::default(), &decks).unwrap();
+            while !state.is_done(&env) {
+                check(&mut state, &cards);
+                let actions = (0..env.player_size)
+                    .map(|player_id| {
+                        let candidates = state.generate_valid_actions(&cards, player_id);
+                        // パスばかりだと盤面が進まないので置ける時は置く
+                        let puts = candidates
+                            .iter()
+                            .filter(|a| !matches!(a, Action::Pass { .. }))
+                            .collect::<Vec<_>>();
+                        if !puts.is_empty() && rng.gen_bool(0.9) {
+                            **puts.choose(&mut rng).unwrap()
+                        } else {
+                            *candidates.choose(&mut rng).unwrap()
+                        }
+                    })
+                    .collect::<Vec<Action>>();
+                state.apply(&env, &cards, &actions).unwrap();
+            }
+            check(&mut state, &cards);
+        }
+    }
     #[test]
     fn test_squares_from_str() {
         let expected = vec![
@@ -1137,46 +1695,204 @@ mod tests {
                 FieldSquareType::Empty,
             ],
         ];
-        let actual = FieldShape::new("yyyyy\nyyyYy\n.y...\ny....").squares;
+        let actual = FieldShape::new("yyyyy\nyyyYy\n.y...\ny....")
+            .unwrap()
+            .squares;
         assert_eq!(actual, expected);
     }
     #[test]
     fn test_activates() {
-        let env = Environment::new(2, 1, 1, 1);
-        let card_catalog = vec![Card::new(1, "hoge", 1, CardShape::new("y"))];
+        let env = Environment::new(2, 1, 1, 1, false);
+        let card_catalog = [Card::new(1, "hoge", 1, CardShape::new("y").unwrap())];
         let mut cards = HashMap::new();
         cards.insert(1usize, &card_catalog[0]);
         let field = Field {
             id: 1,
             name: "hoge".to_string(),
-            shape: FieldShape::new("YB\nbY"),
+            shape: FieldShape::new("YB\nbY").unwrap(),
         };
-        let mut state = State::new(&env, &cards, &field, &vec![vec![1], vec![1]]);
+        let mut state = State::new(&env, &cards, &field, &[vec![1], vec![1]]).unwrap();
         let putted_this_turn_squares = vec![(1, 0)];
-        let actual = state.activates(&putted_this_turn_squares);
-        let expected = vec![2, 1];
+        let actual = state.activates(env.rule_set(), &putted_this_turn_squares);
+        let expected = vec![vec![(0, 0), (1, 1)], vec![(0, 1)]];
         eprintln!("{}", state.field);
         assert_eq!(actual, expected);
     }
     #[test]
+    fn test_activates_with_empty_neighbor() {
+        let env = Environment::new(2, 1, 1, 1, false);
+        let card_catalog = [Card::new(1, "hoge", 1, CardShape::new("y").unwrap())];
+        let mut cards = HashMap::new();
+        cards.insert(1usize, &card_catalog[0]);
+        let field = Field {
+            id: 1,
+            name: "hoge".to_string(),
+            shape: FieldShape::new("Yy.\nyyB").unwrap(),
+        };
+        let start = State::new(&env, &cards, &field, &[vec![1], vec![1]]).unwrap();
+        // 公式ルールでは空白マスに接しているスペシャルマスはポイントを得ずに活性化済みになる
+        let mut state = start.clone();
+        assert_eq!(
+            state.activates(env.rule_set(), &[(1, 1)]),
+            vec![vec![(0, 0)], vec![]]
+        );
+        assert_eq!(
+            state.field.squares[1][2],
+            FieldSquareType::Special {
+                player_id: 1,
+                activeted: true
+            }
+        );
+        state.field.squares[0][2] = FieldSquareType::Colored { player_id: 0 };
+        assert_eq!(
+            state.activates(env.rule_set(), &[(0, 2)]),
+            vec![vec![], vec![]]
+        );
+        // retried_activationでは後で囲まれたときに活性化する
+        let rules = Rules::RetriedActivation.rule_set();
+        let mut state = start;
+        assert_eq!(
+            state.activates(rules, &[(1, 1)]),
+            vec![vec![(0, 0)], vec![]]
+        );
+        state.field.squares[0][2] = FieldSquareType::Colored { player_id: 0 };
+        assert_eq!(
+            state.activates(rules, &[(0, 2)]),
+            vec![vec![], vec![(1, 2)]]
+        );
+    }
+    #[test]
+    fn test_apply_with_rules() {
+        let card_catalog = [
+            Card::new(1, "big", 1, CardShape::new("Yyy").unwrap()),
+            Card::new(2, "small", 1, CardShape::new("Y").unwrap()),
+        ];
+        let cards = card_catalog
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<_, _>>();
+        let field = Field {
+            id: 1,
+            name: "hoge".to_string(),
+            shape: FieldShape::new("Y...B").unwrap(),
+        };
+        let actions = [
+            Action::SpecialPut {
+                card_id: 1,
+                dir: Direction::Up,
+                y: 0,
+                x: 1,
+            },
+            Action::Put {
+                card_id: 2,
+                dir: Direction::Up,
+                y: 0,
+                x: 3,
+            },
+        ];
+        // スペシャルアタックで塗ったマスに同ターンで小さいカードを上塗りできるか
+        let expected = [
+            (
+                Rules::Official,
+                FieldSquareType::Special {
+                    player_id: 1,
+                    activeted: true,
+                },
+            ),
+            (
+                Rules::ProtectedSpecialAttack,
+                FieldSquareType::Colored { player_id: 0 },
+            ),
+        ];
+        for (rules, square) in expected {
+            let env = Environment::new(2, 2, 1, 2, false).with_rules(rules);
+            let mut state = State::new(&env, &cards, &field, &[vec![1, 1], vec![2, 2]]).unwrap();
+            state.players[0].special_point = 1;
+            state.apply(&env, &cards, &actions).unwrap();
+            assert_eq!(state.field.squares[0][3], square, "{}", rules);
+        }
+    }
+    #[test]
+    fn test_turn_outcome() {
+        let env = Environment::new(2, 2, 1, 2, false);
+        let card_catalog = [
+            Card::new(1, "big", 1, CardShape::new("Yyy").unwrap()),
+            Card::new(2, "small", 1, CardShape::new("Y").unwrap()),
+        ];
+        let cards = card_catalog
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<_, _>>();
+        let field = Field {
+            id: 1,
+            name: "hoge".to_string(),
+            shape: FieldShape::new("Y.b.B").unwrap(),
+        };
+        let mut state = State::new(&env, &cards, &field, &[vec![1, 1], vec![2, 2]]).unwrap();
+        state.players[0].special_point = 1;
+        let outcome = state
+            .apply(
+                &env,
+                &cards,
+                &[
+                    Action::SpecialPut {
+                        card_id: 1,
+                        dir: Direction::Up,
+                        y: 0,
+                        x: 1,
+                    },
+                    Action::Put {
+                        card_id: 2,
+                        dir: Direction::Up,
+                        y: 0,
+                        x: 3,
+                    },
+                ],
+            )
+            .unwrap();
+        assert_eq!(outcome.turn, 1);
+        assert_eq!(outcome.painted, vec![vec![(0, 1), (0, 2)], vec![(0, 3)]]);
+        assert!(outcome.blocked.is_empty());
+        assert_eq!(outcome.overpainted, vec![((0, 2), 1)]);
+        assert_eq!(
+            outcome.activated,
+            vec![vec![(0, 0), (0, 1)], vec![(0, 3), (0, 4)]]
+        );
+        assert_eq!(outcome.special_points_from_pass, vec![0, 0]);
+        assert_eq!(outcome.special_points_from_activation, vec![2, 2]);
+        assert_eq!(outcome.special_points_spent, vec![1, 0]);
+        assert_eq!(
+            state
+                .players
+                .iter()
+                .map(|p| p.special_point)
+                .collect::<Vec<_>>(),
+            vec![2, 2]
+        );
+    }
+    #[test]
     fn test_find_reference_point() {
         let expected = (0, 0);
-        let actual = CardShape::new("yyyyy\nyyyYy\n.y...\ny....").find_reference_point(0);
+        let actual = CardShape::new("yyyyy\nyyyYy\n.y...\ny....")
+            .unwrap()
+            .find_reference_point(0);
         assert_eq!(actual, expected);
 
         let expected = (1, 0);
-        let actual = CardShape::new("..\ny.").find_reference_point(0);
+        let actual = CardShape::new("..\ny.").unwrap().find_reference_point(0);
         assert_eq!(actual, expected);
 
         let expected = (0, 2);
-        let actual = CardShape::new("..Y.\ny.yy").find_reference_point(0);
+        let actual = CardShape::new("..Y.\ny.yy")
+            .unwrap()
+            .find_reference_point(0);
         assert_eq!(actual, expected);
     }
 
     #[test]
     fn test_rotate() {
-        let expected = CardShape::new("Yy\n.y\n.y\n..\n..");
-        let shape = CardShape::new("yyy..\nY....");
+        let expected = CardShape::new("Yy\n.y\n.y\n..\n..").unwrap();
+        let shape = CardShape::new("yyy..\nY....").unwrap();
         let actual = shape.rotate();
         assert_eq!(actual, expected);
     }
@@ -1185,15 +1901,276 @@ mod tests {
     fn test_trim() {
         let big = CardShape::new(
             "........\n........\n.yyyyy..\n.yyyYy..\n..y.....\n.y......\n........\n........",
-        );
+        )
+        .unwrap();
         let trimmed = CardShape::trim(&big);
-        let expected = CardShape::new("yyyyy\nyyyYy\n.y...\ny....");
+        let expected = CardShape::new("yyyyy\nyyyYy\n.y...\ny....").unwrap();
         assert_eq!(trimmed, expected);
     }
     #[test]
     fn test_get_cursor() {
-        assert_eq!(get_cursor(0, 0, 1, 2, 3, 7), Some((4, 9)));
-        assert_eq!(get_cursor(4, 0, 1, 2, 3, 7), Some((0, 9)));
-        assert_eq!(get_cursor(4, 10, 1, 2, 3, 7), None);
+        assert_eq!(get_cursor(1, 2, 3, 7), Some((4, 9)));
+        assert_eq!(get_cursor(1, 2, -1, 7), Some((0, 9)));
+        assert_eq!(get_cursor(1, 2, -1, -3), None);
+    }
+    #[test]
+    fn test_orientations_of_catalog_cards() {
+        for card in sample_catalog() {
+            let mut expected = card.shape.clone();
+            for dir in Direction::ALL {
+                let orientation = card.orientation(dir);
+                assert_eq!(orientation.dir, dir);
+                assert_eq!(orientation.shape, expected);
+                assert_eq!(orientation.squares.len(), card.power);
+                // 基準点は最も上の行の最も左のマス
+                assert_eq!(orientation.squares[0].0, 0);
+                assert_eq!(orientation.squares[0].1, 0);
+                assert!(orientation
+                    .squares
+                    .iter()
+                    .all(|&(dy, dx, _)| (dy, dx) >= (0, 0)));
+                // 相対座標から回転済みの形を復元できる
+                let (ry, rx) = orientation.reference_point;
+                let mut restored =
+                    vec![vec![CardSquareType::Empty; expected.width]; expected.height];
+                for &(dy, dx, square_type) in orientation.squares.iter() {
+                    restored[(ry as isize + dy) as usize][(rx as isize + dx) as usize] =
+                        square_type;
+                }
+                assert_eq!(restored, expected.squares);
+                expected = expected.rotate();
+            }
+            assert_eq!(expected, card.shape);
+        }
+    }
+    #[test]
+    fn test_apply_rotated_card() {
+        let env = Environment::new(2, 2, 1, 2, false);
+        let card_catalog = [Card::new(1, "L", 1, CardShape::new("y.\nyY").unwrap())];
+        let mut cards = HashMap::new();
+        cards.insert(1usize, &card_catalog[0]);
+        let field = Field {
+            id: 1,
+            name: "hoge".to_string(),
+            shape: FieldShape::new("....\n....\n.Y..\n...B").unwrap(),
+        };
+        let mut state = State::new(&env, &cards, &field, &[vec![1, 1], vec![1, 1]]).unwrap();
+        let action = Action::Put {
+            card_id: 1,
+            dir: Direction::Right,
+            y: 0,
+            x: 1,
+        };
+        state
+            .apply(&env, &cards, &[action, Action::Pass { card_id: 1 }])
+            .unwrap();
+        assert_eq!(state.field.to_string(), ".yy.\n.Y..\n.Y..\n...B");
+    }
+    #[test]
+    fn test_parse_action() {
+        assert_eq!(
+            "PUT 3 R 4 5".parse::<Action>(),
+            Ok(Action::Put {
+                card_id: 3,
+                dir: Direction::Right,
+                y: 4,
+                x: 5
+            })
+        );
+        assert_eq!(
+            "PASS 3\n".parse::<Action>(),
+            Ok(Action::Pass { card_id: 3 })
+        );
+        assert!("PUT 3 X 4 5".parse::<Action>().is_err());
+        assert!("PUT 3 R 4".parse::<Action>().is_err());
+        assert!("".parse::<Action>().is_err());
+        assert!("MULLIGAN?".parse::<MulliganAction>().is_err());
+        assert!(FieldShape::new("..\n.").is_err());
+        assert!(CardShape::new("yz").is_err());
+    }
+    #[test]
+    fn test_apply_invalid_action() {
+        let env = Environment::new(2, 2, 1, 2, false);
+        let card_catalog = [Card::new(1, "hoge", 1, CardShape::new("y").unwrap())];
+        let mut cards = HashMap::new();
+        cards.insert(1usize, &card_catalog[0]);
+        let field = Field {
+            id: 1,
+            name: "hoge".to_string(),
+            shape: FieldShape::new("Y..\n...\n..B").unwrap(),
+        };
+        let mut state = State::new(&env, &cards, &field, &[vec![1, 1], vec![1, 1]]).unwrap();
+        let put = |y, x| Action::Put {
+            card_id: 1,
+            dir: Direction::Up,
+            y,
+            x,
+        };
+        assert_eq!(
+            state.apply(&env, &cards, &[put(1, 1), Action::Pass { card_id: 2 }]),
+            Err(GameError::CardNotInHand {
+                player_id: 1,
+                card_id: 2
+            })
+        );
+        assert_eq!(
+            state.apply(&env, &cards, &[put(0, 2), Action::Pass { card_id: 1 }]),
+            Err(GameError::NotAdjacent {
+                player_id: 0,
+                card_id: 1
+            })
+        );
+        assert_eq!(
+            state.apply(&env, &cards, &[put(0, 0), Action::Pass { card_id: 1 }]),
+            Err(GameError::Overlap {
+                player_id: 0,
+                card_id: 1,
+                y: 0,
+                x: 0
+            })
+        );
+        assert_eq!(
+            state.apply(&env, &cards, &[put(3, 0), Action::Pass { card_id: 1 }]),
+            Err(GameError::OutOfField {
+                player_id: 0,
+                card_id: 1
+            })
+        );
+        // 不正なアクションでは盤面が変化しない
+        assert_eq!(state.turn, 1);
+        assert!(state
+            .apply(&env, &cards, &[put(1, 1), Action::Pass { card_id: 1 }])
+            .is_ok());
+        assert_eq!(state.turn, 2);
+    }
+    #[test]
+    fn test_multi_player() {
+        assert_eq!(FieldShape::new("gG.\npP.").unwrap().to_string(), "gG.\npP.");
+        let env = Environment::new(3, 2, 1, 2, false);
+        let card_catalog = [Card::new(1, "hoge", 1, CardShape::new("y").unwrap())];
+        let mut cards = HashMap::new();
+        cards.insert(1usize, &card_catalog[0]);
+        let field = Field {
+            id: 1,
+            name: "hoge".to_string(),
+            shape: FieldShape::new("Y.B\n...\n.G.").unwrap(),
+        };
+        let decks = vec![vec![1, 1]; 3];
+        let mut state = State::new(&env, &cards, &field, &decks).unwrap();
+        // 存在しないプレイヤーのマスがあるフィールドは使えない
+        assert!(matches!(
+            State::new(
+                &Environment::new(2, 2, 1, 2, false),
+                &cards,
+                &field,
+                &decks[..2]
+            ),
+            Err(GameError::InvalidField(_))
+        ));
+        let put = |y, x| Action::Put {
+            card_id: 1,
+            dir: Direction::Up,
+            y,
+            x,
+        };
+        // 同じパワーのカードが3枚重なるとブロックになる
+        let outcome = state
+            .apply(&env, &cards, &[put(1, 1), put(1, 1), put(1, 1)])
+            .unwrap();
+        assert_eq!(state.field.to_string(), "Y.B\n.#.\n.G.");
+        assert_eq!(outcome.blocked, vec![(1, 1)]);
+        assert_eq!(outcome.painted, vec![vec![]; 3]);
+        state
+            .apply(
+                &env,
+                &cards,
+                &[put(0, 1), put(1, 2), Action::Pass { card_id: 1 }],
+            )
+            .unwrap();
+        assert!(state.is_done(&env));
+        assert_eq!(state.scores(), vec![2, 2, 1]);
+        assert_eq!(state.ranking(&env), vec![1, 1, 3]);
+        assert!(state.is_draw(&env));
+        assert!(!state.is_win(&env, 0));
+        assert!(state.is_lose(&env, 2));
+    }
+    #[test]
+    fn test_generate_valid_actions_matches_naive() {
+        play_random_games(20, |state, cards| {
+            for player_id in 0..state.players.len() {
+                let expected = state.generate_valid_actions_naive(cards, player_id);
+                assert_eq!(state.generate_valid_actions(cards, player_id), expected);
+            }
+        });
+    }
+    #[test]
+    fn test_incremental_anchors() {
+        play_random_games(20, |state, _| {
+            let anchors = state
+                .players
+                .iter()
+                .map(|p| (p.anchors.clone(), p.special_anchors.clone()))
+                .collect::<Vec<_>>();
+            let all_squares = (0..state.field.height)
+                .flat_map(|y| (0..state.field.width).map(move |x| (y, x)))
+                .collect::<Vec<(usize, usize)>>();
+            state.update_anchors(&all_squares);
+            for (player, (expected, expected_special)) in state.players.iter().zip(anchors) {
+                assert_eq!(player.anchors, expected);
+                assert_eq!(player.special_anchors, expected_special);
+            }
+        });
+    }
+    #[test]
+    fn test_apply_with_undo() {
+        let env = Environment::new(2, 15, 4, 12, true);
+        play_random_games(10, |state, cards| {
+            if state.is_done(&env) {
+                return;
+            }
+            let before = state.clone();
+            let actions = (0..state.players.len())
+                .map(|player_id| {
+                    *state
+                        .generate_valid_actions(cards, player_id)
+                        .last()
+                        .unwrap()
+                })
+                .collect::<Vec<Action>>();
+            let record = state.apply_with_undo(&env, cards, &actions).unwrap();
+            state.undo(record);
+            assert_eq!(*state, before);
+            // 置けるだけ置いてから戻す
+            let actions = (0..state.players.len())
+                .map(|player_id| state.generate_valid_actions(cards, player_id)[0])
+                .collect::<Vec<Action>>();
+            if let Ok(record) = state.apply_with_undo(&env, cards, &actions) {
+                state.undo(record);
+            }
+            assert_eq!(*state, before);
+        });
+    }
+    #[test]
+    fn test_zobrist_hash() {
+        let env = Environment::new(2, 15, 4, 12, true);
+        let mut hashes = HashSet::new();
+        play_random_games(10, |state, cards| {
+            assert_eq!(state.zobrist_hash(), state.compute_zobrist_hash());
+            hashes.insert(state.zobrist_hash());
+            if state.is_done(&env) {
+                return;
+            }
+            let hash = state.zobrist_hash();
+            let actions = (0..state.players.len())
+                .map(|player_id| state.generate_valid_actions(cards, player_id)[0])
+                .collect::<Vec<Action>>();
+            let record = state.apply_with_undo(&env, cards, &actions).unwrap();
+            assert_eq!(state.zobrist_hash(), state.compute_zobrist_hash());
+            assert_ne!(state.zobrist_hash(), hash);
+            state.undo(record);
+            assert_eq!(state.zobrist_hash(), hash);
+        });
+        // 別々の局面はほぼ確実に別のハッシュになる
+        assert!(hashes.len() > 100);
     }
 }
@@ -0,0 +1,423 @@
+//! プロセスを起動せずメモリ上で対局を進める。自己対戦で大量に対局するときに使う。
+use crate::{
+    agent::Agent,
+    check_field,
+    record::{Failure, Forfeit, GameRecord, MoveLog},
+    seed::game_rng,
+    setup::{GameSetup, SetupPhase},
+    Action, Card, CardId, Environment, Field, GameError, MulliganAction, PlayerId, State,
+};
+use log::{debug, warn};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+/// botが持ち時間を過ぎたりプロセスが終了したりしたときの扱い。
+/// 不正な出力は常に反則負けで、デッキを選べなかった場合も対局できないので反則負けにする。
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub enum FailurePolicy {
+    /// 反則負けにして対局を終える
+    #[default]
+    Forfeit,
+    /// マリガンはせず、ターンでは手札のカードでパスする
+    Pass,
+}
+impl Display for FailurePolicy {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            FailurePolicy::Forfeit => write!(f, "forfeit"),
+            FailurePolicy::Pass => write!(f, "pass"),
+        }
+    }
+}
+impl FromStr for FailurePolicy {
+    type Err = GameError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forfeit" => Ok(FailurePolicy::Forfeit),
+            "pass" => Ok(FailurePolicy::Pass),
+            _ => Err(GameError::MalformedText(s.to_string())),
+        }
+    }
+}
+
+pub struct Match;
+impl Match {
+    /// `agents`の順にプレイヤー番号を割り当てて1局を進める。
+    /// 山札のシャッフルは`seed`から作った乱数で行うので、同じ種と同じ行動のbotなら同じ記録になる。
+    /// botの数がプレイヤーの人数と違ったり、フィールドが人数に合わなかったりすると対局せずにエラーを返す。
+    pub fn run(
+        env: &Environment,
+        field: &Field,
+        catalog: &[Card],
+        agents: &mut [Box<dyn Agent>],
+        seed: u64,
+    ) -> Result<GameRecord, GameError> {
+        Self::run_with_policy(env, field, catalog, agents, seed, FailurePolicy::default())
+    }
+    /// `run`と同じだが、botが応答しなくなったときの扱いを`policy`で指定する。
+    /// 対局の後にすべてのbotの`Agent::close`を呼ぶ。
+    pub fn run_with_policy(
+        env: &Environment,
+        field: &Field,
+        catalog: &[Card],
+        agents: &mut [Box<dyn Agent>],
+        seed: u64,
+        policy: FailurePolicy,
+    ) -> Result<GameRecord, GameError> {
+        // 対局の設定の誤りはbotの反則ではないので、始める前に呼び出し元に返す
+        if agents.len() != env.player_size {
+            return Err(GameError::PlayerCountMismatch {
+                expected: env.player_size,
+                actual: agents.len(),
+            });
+        }
+        check_field(env, field)?;
+        let mut record = Self::play(env, field, catalog, agents, seed, policy);
+        record.exit_statuses = agents.iter_mut().map(|agent| agent.close()).collect();
+        for (player_id, status) in record.exit_statuses.iter().enumerate() {
+            if let Some(status) = status {
+                debug!("player {} exited: {}", player_id, status);
+            }
+        }
+        Ok(record)
+    }
+    fn play(
+        env: &Environment,
+        field: &Field,
+        catalog: &[Card],
+        agents: &mut [Box<dyn Agent>],
+        seed: u64,
+        policy: FailurePolicy,
+    ) -> GameRecord {
+        let cards = catalog
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<CardId, &Card>>();
+        let mut record = GameRecord {
+            seed,
+            names: agents
+                .iter()
+                .map(|agent| agent.name().to_string())
+                .collect(),
+            ..Default::default()
+        };
+        let mut state = match Self::start(env, field, catalog, &cards, agents, policy, &mut record)
+        {
+            Ok(state) => state,
+            Err(forfeit) => {
+                record.scores = vec![0; env.player_size];
+                record.set_forfeit(env, forfeit);
+                return record;
+            }
+        };
+        while !state.is_done(env) {
+            if let Err(forfeit) =
+                Self::play_turn(env, &cards, agents, &mut state, policy, &mut record)
+            {
+                record.scores = state.scores();
+                record.set_forfeit(env, forfeit);
+                return record;
+            }
+        }
+        debug!("\n{}", state.field);
+        record.scores = state.scores();
+        record.ranking = state.ranking(env);
+        record.winner = (0..env.player_size).find(|&player_id| state.is_win(env, player_id));
+        record
+    }
+    // 直前の応答の記録を`logs`に加える
+    fn take_move_log(
+        agent: &mut Box<dyn Agent>,
+        player_id: PlayerId,
+        logs: &mut Vec<Option<MoveLog>>,
+    ) {
+        let log = agent.take_move_log();
+        if let Some(log) = log.as_ref().filter(|log| !log.stderr.is_empty()) {
+            debug!("player {} stderr:\n{}", player_id, log.stderr.trim_end());
+        }
+        logs.push(log);
+    }
+    // `policy`が`Pass`で、botが応答しなくなったことによるエラーなら代わりの行動を返す
+    fn recover<T>(
+        policy: FailurePolicy,
+        player_id: PlayerId,
+        turn: usize,
+        reason: GameError,
+        fallback: impl FnOnce() -> T,
+        record: &mut GameRecord,
+    ) -> Result<T, Forfeit> {
+        if policy != FailurePolicy::Pass || !reason.is_bot_failure() {
+            return Err(Forfeit { player_id, reason });
+        }
+        if record
+            .failures
+            .iter()
+            .all(|failure| failure.player_id != player_id)
+        {
+            warn!("player {} failed: {}", player_id, reason);
+            record.failures.push(Failure {
+                player_id,
+                turn,
+                reason,
+            });
+        }
+        Ok(fallback())
+    }
+    fn start(
+        env: &Environment,
+        field: &Field,
+        catalog: &[Card],
+        cards: &HashMap<CardId, &Card>,
+        agents: &mut [Box<dyn Agent>],
+        policy: FailurePolicy,
+        record: &mut GameRecord,
+    ) -> Result<State, Forfeit> {
+        let mut rng = game_rng(record.seed);
+        let mut setup = GameSetup::new(env);
+        for (player_id, agent) in agents.iter_mut().enumerate() {
+            let deck = agent.choose_deck(env, catalog, &field.shape, player_id);
+            Self::take_move_log(agent, player_id, &mut record.deck_logs);
+            let deck = deck.map_err(|reason| Forfeit { player_id, reason })?;
+            debug!("player {} deck: {:?}", player_id, deck);
+            record.picked_decks.push(deck.clone());
+            setup
+                .pick_deck(env, cards, player_id, &deck)
+                .map_err(|reason| Forfeit { player_id, reason })?;
+        }
+        for (player_id, agent) in agents.iter_mut().enumerate() {
+            setup.shuffle(player_id, &mut rng);
+            record.shuffled_decks.push(setup.deck(player_id).to_vec());
+            let action = agent.mulligan(setup.hands(player_id));
+            Self::take_move_log(agent, player_id, &mut record.mulligan_logs);
+            let action = match action {
+                Ok(action) => action,
+                Err(reason) => Self::recover(
+                    policy,
+                    player_id,
+                    0,
+                    reason,
+                    || MulliganAction::Pass,
+                    record,
+                )?,
+            };
+            debug!("player {} mulligan: {}", player_id, action);
+            record.mulligans.push(action);
+            setup.mulligan(player_id, action);
+            if setup.phase(player_id) == SetupPhase::Redeal {
+                setup.shuffle(player_id, &mut rng);
+            }
+        }
+        record.decks = (0..env.player_size)
+            .map(|player_id| setup.deck(player_id).to_vec())
+            .collect();
+        setup
+            .into_state(env, cards, field)
+            .map_err(|reason| Forfeit {
+                player_id: reason
+                    .player_id()
+                    .expect("デッキは選んだ時点で、フィールドは対局の前に検証済み"),
+                reason,
+            })
+    }
+    fn play_turn(
+        env: &Environment,
+        cards: &HashMap<CardId, &Card>,
+        agents: &mut [Box<dyn Agent>],
+        state: &mut State,
+        policy: FailurePolicy,
+        record: &mut GameRecord,
+    ) -> Result<(), Forfeit> {
+        record.turns.push(vec![]);
+        record.turn_logs.push(vec![]);
+        for (player_id, agent) in agents.iter_mut().enumerate() {
+            let valid_actions = state.generate_valid_actions(cards, player_id);
+            debug!(
+                "turn:{}, player_id:{}, n_action:{}",
+                state.turn,
+                player_id,
+                valid_actions.len()
+            );
+            // botにはそのプレイヤーから見える情報だけを渡す
+            let action = agent.act(&state.observe(player_id), &valid_actions);
+            Self::take_move_log(
+                agent,
+                player_id,
+                record.turn_logs.last_mut().expect("直前に追加した"),
+            );
+            let action = match action {
+                Ok(action) => action,
+                Err(reason) => Self::recover(
+                    policy,
+                    player_id,
+                    state.turn,
+                    reason,
+                    || {
+                        *valid_actions
+                            .iter()
+                            .find(|action| matches!(action, Action::Pass { .. }))
+                            .expect("手札のカードでは常にパスできる")
+                    },
+                    record,
+                )?,
+            };
+            debug!("player {} action: {}", player_id, action);
+            record
+                .turns
+                .last_mut()
+                .expect("直前に追加した")
+                .push(action);
+        }
+        let outcome = state
+            .apply(env, cards, record.turns.last().expect("直前に追加した"))
+            .map_err(|reason| Forfeit {
+                player_id: reason
+                    .player_id()
+                    .expect("アクションの数は常にプレイヤー数と一致する"),
+                reason,
+            })?;
+        debug!("{}", outcome);
+        record.outcomes.push(outcome);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::RandomAgent, catalog::parse_card_catalog, observation::Observation, Action,
+        FieldShape, GameError, MulliganAction, PlayerId,
+    };
+    // 対局の途中で不正な行動をするbot
+    struct BrokenAgent {
+        turn: usize,
+        reason: GameError,
+    }
+    impl Agent for BrokenAgent {
+        fn name(&self) -> &str {
+            "broken"
+        }
+        fn choose_deck(
+            &mut self,
+            env: &Environment,
+            cards: &[Card],
+            _field: &FieldShape,
+            _player_id: PlayerId,
+        ) -> Result<Vec<CardId>, GameError> {
+            Ok(cards
+                .iter()
+                .take(env.deck_size)
+                .map(|card| card.id)
+                .collect())
+        }
+        fn mulligan(&mut self, _hands: &[CardId]) -> Result<MulliganAction, GameError> {
+            Ok(MulliganAction::Pass)
+        }
+        fn act(
+            &mut self,
+            observation: &Observation,
+            valid_actions: &[Action],
+        ) -> Result<Action, GameError> {
+            if observation.turn == self.turn {
+                return Err(self.reason.clone());
+            }
+            Ok(valid_actions[0])
+        }
+    }
+    #[test]
+    fn test_run() {
+        let env = Environment::new(2, 6, 4, 3, false);
+        let catalog =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        let run = |seed: u64| {
+            let mut agents: Vec<Box<dyn Agent>> = vec![
+                Box::new(RandomAgent::new("a", game_rng(seed))),
+                Box::new(RandomAgent::new("b", game_rng(seed + 1))),
+            ];
+            Match::run(&env, &Field::default(), &catalog, &mut agents, seed).unwrap()
+        };
+        let record = run(0);
+        assert_eq!(record, run(0));
+        assert_eq!(record.names, vec!["a", "b"]);
+        assert_eq!(record.turns.len(), env.max_turn);
+        assert_eq!(record.decks.len(), env.player_size);
+        assert!(record.forfeit.is_none());
+        assert_eq!(record.scores.len(), env.player_size);
+        assert_eq!(record.turn_logs.len(), record.turns.len());
+        assert_eq!(record.turn_logs[0], vec![None, None]);
+        assert_eq!(record.exit_statuses, vec![None, None]);
+
+        let mut agents: Vec<Box<dyn Agent>> = vec![
+            Box::new(RandomAgent::new("a", game_rng(0))),
+            Box::new(BrokenAgent {
+                turn: 2,
+                reason: GameError::MalformedText("hoge".to_string()),
+            }),
+        ];
+        let record = Match::run(&env, &Field::default(), &catalog, &mut agents, 0).unwrap();
+        assert_eq!(record.turns.len(), 2);
+        assert_eq!(record.turns[1].len(), 1);
+        assert_eq!(record.forfeit.map(|forfeit| forfeit.player_id), Some(1));
+        assert_eq!(record.ranking[1], 2);
+
+        // 3人目のスペシャルマスが無いフィールドでは対局しない
+        let env = Environment::new(3, 6, 4, 3, false);
+        let mut agents: Vec<Box<dyn Agent>> = (0..3)
+            .map(|seed| -> Box<dyn Agent> { Box::new(RandomAgent::new("a", game_rng(seed))) })
+            .collect();
+        assert!(matches!(
+            Match::run(&env, &Field::default(), &catalog, &mut agents, 0),
+            Err(GameError::InvalidField(_))
+        ));
+        assert!(matches!(
+            Match::run(&env, &Field::default(), &catalog, &mut agents[..2], 0),
+            Err(GameError::PlayerCountMismatch { .. })
+        ));
+    }
+    #[test]
+    fn test_run_with_policy() {
+        let env = Environment::new(2, 6, 4, 3, false);
+        let catalog =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        let run = |reason: GameError, policy: FailurePolicy| {
+            let mut agents: Vec<Box<dyn Agent>> = vec![
+                Box::new(RandomAgent::new("a", game_rng(0))),
+                Box::new(BrokenAgent { turn: 2, reason }),
+            ];
+            Match::run_with_policy(&env, &Field::default(), &catalog, &mut agents, 0, policy)
+                .unwrap()
+        };
+        let timeout = GameError::Timeout(std::time::Duration::from_secs(1));
+
+        let record = run(timeout.clone(), FailurePolicy::Forfeit);
+        assert_eq!(
+            record.forfeit.map(|forfeit| forfeit.reason),
+            Some(timeout.clone())
+        );
+
+        // 応答しなかったターンはパスして対局を続ける
+        let record = run(timeout.clone(), FailurePolicy::Pass);
+        assert!(record.forfeit.is_none());
+        assert_eq!(record.turns.len(), env.max_turn);
+        assert!(matches!(record.turns[1][1], Action::Pass { .. }));
+        assert_eq!(
+            record.failures,
+            vec![Failure {
+                player_id: 1,
+                turn: 2,
+                reason: timeout,
+            }]
+        );
+
+        // 不正な出力は反則負けのまま
+        let record = run(
+            GameError::MalformedText("hoge".to_string()),
+            FailurePolicy::Pass,
+        );
+        assert_eq!(record.forfeit.map(|forfeit| forfeit.player_id), Some(1));
+        assert!(record.failures.is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+//! プレイヤーから見える情報だけを取り出した局面。
+//! 相手の手札や山札の順番は含まないので、botに渡しても情報が漏れない。
+use crate::{Action, CardId, FieldShape, PlayerId, State};
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Observation {
+    /// 観測しているプレイヤー
+    pub player_id: PlayerId,
+    pub turn: usize,
+    pub field: FieldShape,
+    /// 全員のスペシャルポイント
+    pub special_points: Vec<usize>,
+    /// 自分の手札
+    pub hands: Vec<CardId>,
+    /// 自分の山札に残っているカード。順番は分からないので昇順に並べてある。
+    pub deck: Vec<CardId>,
+    /// 全員の手札の枚数
+    pub hand_sizes: Vec<usize>,
+    /// 全員の山札の残り枚数
+    pub deck_sizes: Vec<usize>,
+    /// 全員がこれまでのターンに出したアクション
+    pub played: Vec<Vec<Action>>,
+}
+impl Observation {
+    /// プレイヤーがこれまでに出したカード
+    pub fn played_cards(&self, player_id: PlayerId) -> Vec<CardId> {
+        self.played[player_id]
+            .iter()
+            .map(|action| action.get_card_id())
+            .collect()
+    }
+}
+
+impl State {
+    /// `player_id`のプレイヤーが知りうる情報だけを返す
+    pub fn observe(&self, player_id: PlayerId) -> Observation {
+        let player = &self.players[player_id];
+        let mut deck = player.deck.iter().copied().collect::<Vec<CardId>>();
+        deck.sort();
+        Observation {
+            player_id,
+            turn: self.turn,
+            field: self.field.clone(),
+            special_points: self.players.iter().map(|p| p.special_point).collect(),
+            hands: player.hands.clone(),
+            deck,
+            hand_sizes: self.players.iter().map(|p| p.hands.len()).collect(),
+            deck_sizes: self.players.iter().map(|p| p.deck.len()).collect(),
+            played: self.players.iter().map(|p| p.played.clone()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Card, CardShape, Direction, Environment, Field};
+    use std::collections::HashMap;
+    #[test]
+    fn test_observe() {
+        let env = Environment::new(2, 3, 1, 2, true);
+        let card_catalog = [
+            Card::new(1, "a", 1, CardShape::new("Y").unwrap()),
+            Card::new(2, "b", 1, CardShape::new("Y").unwrap()),
+            Card::new(3, "c", 1, CardShape::new("Y").unwrap()),
+        ];
+        let cards = card_catalog
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<_, _>>();
+        let field = Field {
+            id: 1,
+            name: "hoge".to_string(),
+            shape: FieldShape::new("Y...B\n.....").unwrap(),
+        };
+        let mut state = State::new(&env, &cards, &field, &[vec![3, 1, 2], vec![2, 2, 1]]).unwrap();
+        let actions = [
+            Action::Put {
+                card_id: 3,
+                dir: Direction::Up,
+                y: 0,
+                x: 1,
+            },
+            Action::Pass { card_id: 2 },
+        ];
+        state.apply(&env, &cards, &actions).unwrap();
+
+        let observation = state.observe(0);
+        assert_eq!(observation.turn, 2);
+        assert_eq!(observation.field, state.field);
+        assert_eq!(observation.special_points, vec![0, 1]);
+        assert_eq!(observation.hands, vec![1]);
+        assert_eq!(observation.deck, vec![2]);
+        assert_eq!(observation.hand_sizes, vec![1, 1]);
+        assert_eq!(observation.deck_sizes, vec![1, 1]);
+        assert_eq!(observation.played_cards(1), vec![2]);
+        // 相手の手札は見えない
+        assert_eq!(state.observe(1).hands, vec![2]);
+        assert_eq!(state.observe(1).deck, vec![1]);
+    }
+}
@@ -0,0 +1,121 @@
+//! 対局の記録。`match_runner::Match::run`が作り、judgeは棋譜として書き出す。
+use crate::{Action, CardId, Environment, GameError, MulliganAction, PlayerId, TurnOutcome};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// 不正な入力をしたプレイヤーの反則負け
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Forfeit {
+    pub player_id: PlayerId,
+    pub reason: GameError,
+}
+
+/// 持ち時間切れやプロセスの終了で、botの代わりに自動でパスした記録
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Failure {
+    pub player_id: PlayerId,
+    /// 応答しなくなったターン。マリガンのときは0。
+    pub turn: usize,
+    pub reason: GameError,
+}
+
+/// botの1回の応答の記録。`Agent::take_move_log`が返す。
+#[derive(Eq, PartialEq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MoveLog {
+    /// 入力を送ってから応答を受け取るまでの時間
+    pub elapsed: Duration,
+    /// 前回の応答からこの応答までにbotが標準エラー出力に書いた内容
+    pub stderr: String,
+    /// `stderr`の上限を超えて捨てたバイト数
+    pub stderr_dropped: usize,
+}
+
+/// 1局分の記録。反則で途中で終わった場合は、そこまでに決まった分だけが入る。
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct GameRecord {
+    pub seed: u64,
+    pub names: Vec<String>,
+    /// 各プレイヤーが選んだデッキ
+    pub picked_decks: Vec<Vec<CardId>>,
+    /// 最初にシャッフルした山札の順番
+    pub shuffled_decks: Vec<Vec<CardId>>,
+    pub mulligans: Vec<MulliganAction>,
+    /// マリガンの後の山札の順番。対局はこの順番で始まる。
+    pub decks: Vec<Vec<CardId>>,
+    /// ターンごとの全員のアクション。反則で終わったターンは途中までになる。
+    pub turns: Vec<Vec<Action>>,
+    /// 最後まで行動がそろったターンの`State::apply`の結果
+    pub outcomes: Vec<TurnOutcome>,
+    /// 各プレイヤーのマス数
+    pub scores: Vec<usize>,
+    /// 各プレイヤーの順位。1が最上位。
+    pub ranking: Vec<usize>,
+    pub winner: Option<PlayerId>,
+    pub forfeit: Option<Forfeit>,
+    /// 反則負けにせず自動でパスしたbot。プレイヤーごとに最初の1回だけを記録する。
+    pub failures: Vec<Failure>,
+    /// 各プレイヤーのデッキ選択・マリガンの応答の記録。記録しないbotは`None`。
+    pub deck_logs: Vec<Option<MoveLog>>,
+    pub mulligan_logs: Vec<Option<MoveLog>>,
+    /// `turns`と同じ並びの各アクションの応答の記録。反則で終わったターンは反則した応答まで入る。
+    pub turn_logs: Vec<Vec<Option<MoveLog>>>,
+    /// 対局の後のbotの終了状態。プロセス内のbotは`None`。
+    pub exit_statuses: Vec<Option<String>>,
+}
+impl GameRecord {
+    /// 反則したプレイヤーを最下位とし、残りのプレイヤーは`scores`で順位を付ける
+    pub fn set_forfeit(&mut self, env: &Environment, forfeit: Forfeit) {
+        let ranking = (0..env.player_size)
+            .map(|player_id| {
+                if player_id == forfeit.player_id {
+                    env.player_size
+                } else {
+                    1 + (0..env.player_size)
+                        .filter(|&other| {
+                            other != forfeit.player_id
+                                && self.scores[other] > self.scores[player_id]
+                        })
+                        .count()
+                }
+            })
+            .collect::<Vec<usize>>();
+        self.winner = if ranking.iter().filter(|&&rank| rank == 1).count() == 1 {
+            ranking.iter().position(|&rank| rank == 1)
+        } else {
+            None
+        };
+        self.ranking = ranking;
+        self.forfeit = Some(forfeit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_set_forfeit() {
+        let env = Environment::new(3, 3, 1, 2, true);
+        let mut record = GameRecord {
+            scores: vec![5, 3, 3],
+            ..Default::default()
+        };
+        record.set_forfeit(
+            &env,
+            Forfeit {
+                player_id: 0,
+                reason: GameError::MalformedText("".to_string()),
+            },
+        );
+        assert_eq!(record.ranking, vec![3, 1, 1]);
+        assert_eq!(record.winner, None);
+        record.set_forfeit(
+            &env,
+            Forfeit {
+                player_id: 1,
+                reason: GameError::MalformedText("".to_string()),
+            },
+        );
+        assert_eq!(record.ranking, vec![1, 3, 2]);
+        assert_eq!(record.winner, Some(0));
+    }
+}
@@ -0,0 +1,207 @@
+//! ルールの解釈が分かれる部分を差し替えられるようにする。
+//! `Environment::rules`でどの解釈を使うかを選び、`State::apply`などから呼び出す。
+use crate::{CardSquareType, Environment, FieldShape, FieldSquareType, PlayerId, State, DYDX8};
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+/// 同じターンにカードが塗ったマス
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Paint {
+    pub player_id: PlayerId,
+    pub power: usize,
+    pub square_type: CardSquareType,
+    /// スペシャルアタックで塗ったか
+    pub special_attack: bool,
+}
+
+/// 同じターンに塗られたマスに別のカードのマスが重なったときの結果
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Overlap {
+    /// 後から塗ったマスで上書きする
+    Overwrite,
+    /// 先に塗ったマスを残す
+    Keep,
+    /// ブロックマスにする
+    Block,
+}
+
+/// ゲームのルールのうち、解釈や変種によって変わりうる部分。
+/// 既定の実装は公式ルールの解釈になっている。
+pub trait RuleSet: Sync {
+    /// 同じターンに塗られたマスに重なったときの処理。
+    /// カードはパワーの大きい順に処理されるので、`incoming`のパワーは`painted`以下になる。
+    fn resolve_overlap(&self, painted: &Paint, incoming: &Paint) -> Overlap {
+        if painted.square_type == incoming.square_type {
+            if painted.power == incoming.power {
+                self.resolve_conflict(painted, incoming)
+            } else {
+                // マス数の少ないカードが優先される
+                Overlap::Overwrite
+            }
+        } else if incoming.square_type == CardSquareType::Special {
+            // スペシャルマスは通常マスより優先される
+            Overlap::Overwrite
+        } else {
+            Overlap::Keep
+        }
+    }
+    /// 同じパワーのカードの同じ種類のマスが重なったときの処理
+    fn resolve_conflict(&self, _painted: &Paint, _incoming: &Paint) -> Overlap {
+        Overlap::Block
+    }
+    /// パスしたときに得るスペシャルポイント
+    fn special_points_for_pass(&self) -> usize {
+        1
+    }
+    /// スペシャルマスが1つ活性化したときに得るスペシャルポイント
+    fn special_points_for_activation(&self) -> usize {
+        1
+    }
+    /// (y, x)のスペシャルマスが活性化する条件を満たしているか。
+    /// 周囲8マスに空白マスが無ければ活性化する。
+    fn is_activated(&self, field: &FieldShape, y: usize, x: usize) -> bool {
+        DYDX8.iter().all(|&(dy, dx)| {
+            let ny = y.wrapping_add(dy);
+            let nx = x.wrapping_add(dx);
+            field.height <= ny
+                || field.width <= nx
+                || !matches!(field.squares[ny][nx], FieldSquareType::Empty)
+        })
+    }
+    /// スペシャルマスの活性化を、周りにカードが置かれた最初のターンだけ判定するか。
+    /// trueなら条件を満たさなくても活性化済みにし、以降はスペシャルポイントを得られない。
+    fn checks_activation_once(&self) -> bool {
+        true
+    }
+    /// プレイヤーごとの順位（1位が1）。マス数の多い順に並べ、同じマス数は同じ順位にする。
+    fn ranking(&self, state: &State) -> Vec<usize> {
+        let scores = state.scores();
+        scores
+            .iter()
+            .map(|score| 1 + scores.iter().filter(|&other| other > score).count())
+            .collect()
+    }
+    /// ターンの終わりに山札からカードを1枚引くか。`state.turn`は次のターンを指している。
+    fn draws_card(&self, env: &Environment, state: &State) -> bool {
+        !state.is_done(env)
+    }
+}
+
+/// 公式ルール
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct OfficialRules;
+impl RuleSet for OfficialRules {}
+
+/// スペシャルアタックで塗ったマスは、同じターンにマス数の少ないカードで上塗りされない
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct ProtectedSpecialAttackRules;
+impl RuleSet for ProtectedSpecialAttackRules {
+    fn resolve_overlap(&self, painted: &Paint, incoming: &Paint) -> Overlap {
+        if painted.special_attack && !incoming.special_attack && incoming.power < painted.power {
+            Overlap::Keep
+        } else {
+            OfficialRules.resolve_overlap(painted, incoming)
+        }
+    }
+}
+
+/// 周囲に空白マスが残っていて活性化しなかったスペシャルマスは、後のターンで囲まれたときに活性化する
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct RetriedActivationRules;
+impl RuleSet for RetriedActivationRules {
+    fn checks_activation_once(&self) -> bool {
+        false
+    }
+}
+
+/// `Environment`で選べるルール
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub enum Rules {
+    #[default]
+    Official,
+    ProtectedSpecialAttack,
+    RetriedActivation,
+}
+impl Rules {
+    pub const ALL: [Rules; 3] = [
+        Rules::Official,
+        Rules::ProtectedSpecialAttack,
+        Rules::RetriedActivation,
+    ];
+    pub fn rule_set(&self) -> &'static dyn RuleSet {
+        match self {
+            Rules::Official => &OfficialRules,
+            Rules::ProtectedSpecialAttack => &ProtectedSpecialAttackRules,
+            Rules::RetriedActivation => &RetriedActivationRules,
+        }
+    }
+}
+impl Display for Rules {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Rules::Official => write!(f, "official"),
+            Rules::ProtectedSpecialAttack => write!(f, "protected_special_attack"),
+            Rules::RetriedActivation => write!(f, "retried_activation"),
+        }
+    }
+}
+impl FromStr for Rules {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Rules::ALL
+            .into_iter()
+            .find(|rules| rules.to_string() == s)
+            .ok_or_else(|| format!("unknown rules: {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn paint(player_id: PlayerId, power: usize, special_attack: bool) -> Paint {
+        Paint {
+            player_id,
+            power,
+            square_type: CardSquareType::Colored,
+            special_attack,
+        }
+    }
+    #[test]
+    fn test_resolve_overlap() {
+        let rules = Rules::Official.rule_set();
+        assert_eq!(
+            rules.resolve_overlap(&paint(0, 5, false), &paint(1, 5, false)),
+            Overlap::Block
+        );
+        assert_eq!(
+            rules.resolve_overlap(&paint(0, 5, true), &paint(1, 3, false)),
+            Overlap::Overwrite
+        );
+        let special = Paint {
+            square_type: CardSquareType::Special,
+            ..paint(1, 3, false)
+        };
+        assert_eq!(
+            rules.resolve_overlap(&special, &paint(0, 2, false)),
+            Overlap::Keep
+        );
+        let rules = Rules::ProtectedSpecialAttack.rule_set();
+        assert_eq!(
+            rules.resolve_overlap(&paint(0, 5, true), &paint(1, 3, false)),
+            Overlap::Keep
+        );
+        assert_eq!(
+            rules.resolve_overlap(&paint(0, 5, true), &paint(1, 3, true)),
+            Overlap::Overwrite
+        );
+    }
+    #[test]
+    fn test_parse_rules() {
+        for rules in Rules::ALL {
+            assert_eq!(rules.to_string().parse::<Rules>(), Ok(rules));
+        }
+        assert!("hoge".parse::<Rules>().is_err());
+    }
+}
@@ -0,0 +1,36 @@
+//! 対局を再現するための乱数の種。
+//! judgeは1つの種から山札のシャッフルやbotに渡す種をすべて作る。
+use crate::zobrist;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// 対局で使う乱数生成器。`rand`の`StdRng`と違い、同じ種からは常に同じ乱数列になる。
+pub type GameRng = ChaCha8Rng;
+/// botに対局ごとの種を渡す環境変数
+pub const SEED_ENV_VAR: &str = "TABLETURF_SEED";
+
+pub fn game_rng(seed: u64) -> GameRng {
+    GameRng::seed_from_u64(seed)
+}
+/// 1つの種から用途ごとに別の種を作る
+pub fn derive_seed(seed: u64, index: u64) -> u64 {
+    zobrist::key(&[seed, index])
+}
+/// judgeから渡された種。渡されていない場合や読めない場合はNone。
+pub fn seed_from_env() -> Option<u64> {
+    std::env::var(SEED_ENV_VAR).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    #[test]
+    fn test_game_rng_is_reproducible() {
+        let a = (0..8).map(|_| game_rng(1).gen::<u64>()).collect::<Vec<_>>();
+        assert!(a.iter().all(|&v| v == a[0]));
+        assert_ne!(game_rng(1).gen::<u64>(), game_rng(2).gen::<u64>());
+        assert_ne!(derive_seed(1, 0), derive_seed(1, 1));
+        assert_eq!(derive_seed(1, 0), derive_seed(1, 0));
+    }
+}
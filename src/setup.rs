@@ -0,0 +1,198 @@
+//! 対局を始めるまでの手順。デッキを選び、シャッフルして手札を配り、マリガンを受け付けてから`State`を作る。
+//! judge・record_player・プロセス内のbotはすべてこの手順で対局を始める。
+use crate::{
+    deck::Deck, Card, CardId, Environment, Field, GameError, MulliganAction, PlayerId, State,
+};
+use rand::{seq::SliceRandom, Rng};
+use std::collections::HashMap;
+
+/// プレイヤーごとの準備の段階
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum SetupPhase {
+    /// デッキを選ぶ
+    PickDeck,
+    /// シャッフルして手札を配る
+    Deal,
+    /// 配られた手札を見てマリガンするか決める
+    Mulligan,
+    /// マリガンしたので配り直す
+    Redeal,
+    /// 準備が終わった
+    Ready,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct GameSetup {
+    hand_size: usize,
+    phases: Vec<SetupPhase>,
+    picked_decks: Vec<Vec<CardId>>,
+    /// 山札の順番。先頭の`hand_size`枚が手札になる。
+    decks: Vec<Vec<CardId>>,
+    mulligans: Vec<Option<MulliganAction>>,
+}
+impl GameSetup {
+    pub fn new(env: &Environment) -> Self {
+        Self {
+            hand_size: env.hand_size,
+            phases: vec![SetupPhase::PickDeck; env.player_size],
+            picked_decks: vec![vec![]; env.player_size],
+            decks: vec![vec![]; env.player_size],
+            mulligans: vec![None; env.player_size],
+        }
+    }
+    pub fn phase(&self, player_id: PlayerId) -> SetupPhase {
+        self.phases[player_id]
+    }
+    /// 全員の準備が終わったか
+    pub fn is_ready(&self) -> bool {
+        self.phases.iter().all(|&phase| phase == SetupPhase::Ready)
+    }
+    /// 選んだデッキ
+    pub fn picked_deck(&self, player_id: PlayerId) -> &[CardId] {
+        &self.picked_decks[player_id]
+    }
+    /// 今の山札の順番
+    pub fn deck(&self, player_id: PlayerId) -> &[CardId] {
+        &self.decks[player_id]
+    }
+    /// 配られた手札
+    pub fn hands(&self, player_id: PlayerId) -> &[CardId] {
+        &self.decks[player_id][..self.hand_size.min(self.decks[player_id].len())]
+    }
+    pub fn mulligan_action(&self, player_id: PlayerId) -> Option<MulliganAction> {
+        self.mulligans[player_id]
+    }
+    pub fn pick_deck(
+        &mut self,
+        env: &Environment,
+        cards: &HashMap<CardId, &Card>,
+        player_id: PlayerId,
+        deck: &[CardId],
+    ) -> Result<(), GameError> {
+        self.expect_phase(player_id, &[SetupPhase::PickDeck]);
+        let deck = Deck::new(deck.to_vec())
+            .validate(env, cards)
+            .map_err(|reason| GameError::InvalidDeck { player_id, reason })?;
+        self.picked_decks[player_id] = deck.cards.clone();
+        self.decks[player_id] = deck.cards;
+        self.phases[player_id] = SetupPhase::Deal;
+        Ok(())
+    }
+    /// 山札をシャッフルして手札を配る。マリガンした後の配り直しにも使う。
+    pub fn shuffle(&mut self, player_id: PlayerId, rng: &mut impl Rng) {
+        let mut deck = self.picked_decks[player_id].clone();
+        deck.shuffle(rng);
+        self.deal(player_id, &deck)
+            .expect("選んだデッキを並べ替えただけなので一致する");
+    }
+    /// 決まった順番の山札から手札を配る。棋譜の再生などシャッフル済みの順番が分かっている場合に使う。
+    pub fn deal(&mut self, player_id: PlayerId, deck: &[CardId]) -> Result<(), GameError> {
+        self.expect_phase(player_id, &[SetupPhase::Deal, SetupPhase::Redeal]);
+        let mut sorted = deck.to_vec();
+        sorted.sort();
+        let mut picked = self.picked_decks[player_id].clone();
+        picked.sort();
+        if sorted != picked {
+            return Err(GameError::DeckMismatch { player_id });
+        }
+        self.decks[player_id] = deck.to_vec();
+        self.phases[player_id] = match self.phases[player_id] {
+            SetupPhase::Deal => SetupPhase::Mulligan,
+            _ => SetupPhase::Ready,
+        };
+        Ok(())
+    }
+    /// マリガンした場合は配り直しの段階に進む
+    pub fn mulligan(&mut self, player_id: PlayerId, action: MulliganAction) {
+        self.expect_phase(player_id, &[SetupPhase::Mulligan]);
+        self.mulligans[player_id] = Some(action);
+        self.phases[player_id] = match action {
+            MulliganAction::Pass => SetupPhase::Ready,
+            MulliganAction::Mulligan => SetupPhase::Redeal,
+        };
+    }
+    /// 準備の終わった山札から対局を始める
+    pub fn into_state(
+        self,
+        env: &Environment,
+        cards: &HashMap<CardId, &Card>,
+        field: &Field,
+    ) -> Result<State, GameError> {
+        assert!(
+            self.is_ready(),
+            "全員の準備が終わっていない: {:?}",
+            self.phases
+        );
+        State::new(env, cards, field, &self.decks)
+    }
+    // 手順を飛ばすのは呼び出し側の誤りなのでpanicする
+    fn expect_phase(&self, player_id: PlayerId, expected: &[SetupPhase]) {
+        assert!(
+            expected.contains(&self.phases[player_id]),
+            "player {}は{:?}の段階で、{:?}の段階ではない",
+            player_id,
+            self.phases[player_id],
+            expected
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{deck::DeckError, seed::game_rng, CardShape};
+    #[test]
+    fn test_game_setup() {
+        let env = Environment::new(2, 3, 2, 2, true);
+        let card_catalog = [
+            Card::new(1, "a", 1, CardShape::new("Y").unwrap()),
+            Card::new(2, "b", 1, CardShape::new("Y").unwrap()),
+            Card::new(3, "c", 1, CardShape::new("Y").unwrap()),
+        ];
+        let cards = card_catalog
+            .iter()
+            .map(|card| (card.id, card))
+            .collect::<HashMap<_, _>>();
+        let mut rng = game_rng(0);
+        let mut setup = GameSetup::new(&env);
+        assert_eq!(
+            setup.pick_deck(&env, &cards, 0, &[1, 2]),
+            Err(GameError::InvalidDeck {
+                player_id: 0,
+                reason: DeckError::WrongSize {
+                    expected: 3,
+                    actual: 2
+                }
+            })
+        );
+        setup.pick_deck(&env, &cards, 0, &[1, 2, 3]).unwrap();
+        setup.pick_deck(&env, &cards, 1, &[3, 3, 3]).unwrap();
+        assert_eq!(setup.phase(0), SetupPhase::Deal);
+
+        setup.shuffle(0, &mut rng);
+        assert_eq!(setup.hands(0).len(), 2);
+        setup.mulligan(0, MulliganAction::Mulligan);
+        assert_eq!(setup.phase(0), SetupPhase::Redeal);
+        assert_eq!(
+            setup.deal(0, &[1, 1, 2]),
+            Err(GameError::DeckMismatch { player_id: 0 })
+        );
+        setup.deal(0, &[3, 1, 2]).unwrap();
+        assert_eq!(setup.phase(0), SetupPhase::Ready);
+
+        setup.deal(1, &[3, 3, 3]).unwrap();
+        assert!(!setup.is_ready());
+        setup.mulligan(1, MulliganAction::Pass);
+        assert!(setup.is_ready());
+        let state = setup.into_state(&env, &cards, &Field::default()).unwrap();
+        assert_eq!(state.players[0].hands, vec![3, 1]);
+        assert_eq!(state.players[0].deck, vec![2]);
+    }
+    #[test]
+    #[should_panic]
+    fn test_game_setup_rejects_skipped_steps() {
+        let env = Environment::new(2, 3, 2, 2, true);
+        let mut setup = GameSetup::new(&env);
+        setup.mulligan(0, MulliganAction::Pass);
+    }
+}
@@ -1,5 +1,8 @@
-use crate::{Card, CardShape, FieldShape};
-use std::io;
+use crate::{
+    agent::Agent, catalog::new_card, observation::Observation, record::GameRecord, Action, Card,
+    CardId, Environment, FieldShape, PlayerId,
+};
+use std::io::{self, BufRead, Write};
 
 #[macro_export]
 macro_rules! read_line {
@@ -22,59 +25,65 @@ macro_rules! parse_input {
     };
 }
 
+// `read_line!`と同じく1行を空白で区切る
+fn read_words(reader: &mut impl BufRead) -> Vec<String> {
+    let mut buff = String::new();
+    reader.read_line(&mut buff).expect("標準入力から読める");
+    buff.split_whitespace().map(|s| s.to_string()).collect()
+}
+
 pub struct InitialInput {
     pub player_size: usize,
     pub deck_size: usize,
     pub hand_size: usize,
     pub max_turn: usize,
     pub is_deplicated_pick_enabled: bool,
+    /// 自分のプレイヤー番号。送られなかった場合は0とみなす。
+    pub player_id: PlayerId,
     pub field_size_y: usize,
     pub field_size_x: usize,
     pub field: FieldShape,
     pub cards: Vec<Card>,
 }
-pub fn read_initial_input() -> InitialInput {
-    let chunks = read_line!();
+pub fn read_initial_input(reader: &mut impl BufRead) -> InitialInput {
+    let chunks = read_words(reader);
     let player_size = parse_input!(chunks[0], usize);
     let deck_size = parse_input!(chunks[1], usize);
     let hand_size = parse_input!(chunks[2], usize);
     let max_turn = parse_input!(chunks[3], usize);
     let is_deplicated_pick_enabled = parse_input!(chunks[4], usize);
+    let player_id = chunks.get(5).map_or(0, |s| parse_input!(s, usize));
 
-    let chunks = read_line!();
+    let chunks = read_words(reader);
     let field_size_y = parse_input!(chunks[0], usize);
     let field_size_x = parse_input!(chunks[1], usize);
 
     let mut rows = vec![];
     for _ in 0..field_size_y {
-        let chunks = read_line!();
+        let chunks = read_words(reader);
         rows.push(chunks.join(""))
     }
     assert!(rows.iter().all(|row| row.len() == field_size_x));
-    let field = FieldShape::new(&rows.join("\n"));
+    let field = FieldShape::new(&rows.join("\n")).expect("judgeからの入力は正しい形式");
 
-    let chunks = read_line!();
+    let chunks = read_words(reader);
     let n_cards = parse_input!(chunks[0], usize);
     let mut cards = vec![];
     for _ in 0..n_cards {
-        let chunks = read_line!();
+        let chunks = read_words(reader);
         let card_id = parse_input!(chunks[0], usize);
         let card_cost = parse_input!(chunks[1], usize);
         let card_size_y = parse_input!(chunks[2], usize);
         let _card_size_x = parse_input!(chunks[3], usize);
         let mut rows = vec![];
         for _ in 0..card_size_y {
-            let chunks = read_line!();
+            let chunks = read_words(reader);
             rows.push(chunks.join(""))
         }
-        let card_shape = CardShape::new(&rows.join("\n"));
-        cards.push(Card {
-            id: card_id,
-            name: "".to_string(),
-            cost: card_cost,
-            power: card_shape.count_colored_squares(),
-            shape: card_shape,
-        })
+        cards.push(
+            new_card(card_id, "", card_cost, &rows.join("\n"))
+                .expect("judgeからの入力は正しい形式"),
+        )
     }
 
     InitialInput {
@@ -83,6 +92,7 @@ pub fn read_initial_input() -> InitialInput {
         hand_size,
         max_turn,
         is_deplicated_pick_enabled: is_deplicated_pick_enabled == 1,
+        player_id,
         field_size_y,
         field_size_x,
         field,
@@ -90,11 +100,225 @@ pub fn read_initial_input() -> InitialInput {
     }
 }
 
-pub fn read_hands() -> Vec<usize> {
-    let chunks = read_line!();
+pub fn read_hands(reader: &mut impl BufRead) -> Vec<usize> {
+    let chunks = read_words(reader);
     let hands = chunks
         .iter()
         .map(|s| s.parse::<usize>().unwrap())
         .collect::<Vec<usize>>();
     hands
 }
+
+/// 毎ターンjudgeから送られる入力
+pub struct TurnInput {
+    pub turn: usize,
+    pub special_points: Vec<usize>,
+    pub field: FieldShape,
+    pub hands: Vec<CardId>,
+    pub valid_actions: Vec<Action>,
+}
+pub fn read_turn_input(reader: &mut impl BufRead, field_size_y: usize) -> TurnInput {
+    let chunks = read_words(reader);
+    let turn = parse_input!(chunks[0], usize);
+
+    let chunks = read_words(reader);
+    let special_points = chunks
+        .iter()
+        .map(|s| s.parse::<usize>().unwrap())
+        .collect::<Vec<usize>>();
+
+    let mut rows = vec![];
+    for _ in 0..field_size_y {
+        let chunks = read_words(reader);
+        rows.push(chunks.join(""))
+    }
+    let field = FieldShape::new(&rows.join("\n")).expect("judgeからの入力は正しい形式");
+
+    let hands = read_hands(reader);
+    let chunks = read_words(reader);
+    let n_actions = parse_input!(chunks[0], usize);
+    let mut valid_actions = vec![];
+    for _ in 0..n_actions {
+        let chunks = read_words(reader);
+        valid_actions.push(
+            chunks
+                .join(" ")
+                .parse::<Action>()
+                .expect("judgeからの入力は正しい形式"),
+        );
+    }
+    TurnInput {
+        turn,
+        special_points,
+        field,
+        hands,
+        valid_actions,
+    }
+}
+
+/// カードIDを空白区切りで並べる。デッキと手札の行に使う。
+pub fn format_card_ids(card_ids: &[CardId]) -> String {
+    card_ids
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+/// 対局開始時の入力。`player_id`はbotに送る場合だけ1行目の末尾に付け、棋譜には付けない。
+pub fn format_initial_input(
+    env: &Environment,
+    cards: &[Card],
+    field: &FieldShape,
+    player_id: Option<PlayerId>,
+) -> String {
+    let cards_info = cards
+        .iter()
+        .map(|card| {
+            format!(
+                "{} {} {} {}\n{}",
+                card.id, card.cost, card.shape.height, card.shape.width, card.shape
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!(
+        "{player_size} {deck_size} {hand_size} {max_turn} {is_deplicated_pick_enabled}{player_id}\n{stage_size_y} {stage_size_x}\n{stage_shape}\n{number_of_cards}\n{cards_info}\n",
+        player_size = env.player_size,
+        deck_size = env.deck_size,
+        hand_size = env.hand_size,
+        max_turn = env.max_turn,
+        is_deplicated_pick_enabled = if env.is_deplicated_pick_enabled{"1"}else{"0"},
+        player_id = player_id.map_or(String::new(), |player_id| format!(" {}", player_id)),
+        stage_size_y = field.height,
+        stage_size_x = field.width,
+        stage_shape = field,
+        number_of_cards = cards.len(),
+        cards_info = cards_info
+    )
+}
+/// 毎ターンの入力。botにはそのプレイヤーから見える情報だけを渡す。
+pub fn format_turn_input(observation: &Observation, valid_actions: &[Action]) -> String {
+    format!(
+        "{turn}\n{special_points}\n{stage_shape}\n{hands}\n{n_action}\n{valid_actions}\n",
+        turn = observation.turn,
+        special_points = observation
+            .special_points
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<String>>()
+            .join(" "),
+        stage_shape = observation.field,
+        hands = format_card_ids(&observation.hands),
+        n_action = valid_actions.len(),
+        valid_actions = valid_actions
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    )
+}
+
+/// judgeが書き出す棋譜。record_playerはこの形式を読む。
+pub fn format_record(
+    env: &Environment,
+    cards: &[Card],
+    field: &FieldShape,
+    record: &GameRecord,
+) -> String {
+    let mut lines = vec![format!("SEED {}", record.seed)];
+    // 末尾の改行は`join`で補う
+    lines.push(
+        format_initial_input(env, cards, field, None)
+            .trim_end()
+            .to_string(),
+    );
+    lines.extend(record.names.iter().cloned());
+    lines.extend(record.picked_decks.iter().map(|deck| format_card_ids(deck)));
+    for (player_id, deck) in record.shuffled_decks.iter().enumerate() {
+        lines.push(format_card_ids(deck));
+        if let Some(action) = record.mulligans.get(player_id) {
+            lines.push(action.to_string());
+        }
+    }
+    lines.extend(record.decks.iter().map(|deck| format_card_ids(deck)));
+    lines.extend(
+        record
+            .turns
+            .iter()
+            .flatten()
+            .map(|action| action.to_string()),
+    );
+    lines.join("\n") + "\n"
+}
+
+/// `Agent`をjudgeとテキストでやり取りするbotとして動かす。最終ターンに行動したら戻る。
+/// テキストでは相手の出したカードが送られないので、`Observation`の相手の`played`は空になる。
+pub fn run_text_protocol(
+    agent: &mut impl Agent,
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(writer, "{}", agent.name())?;
+    writer.flush()?;
+
+    let initial_input = read_initial_input(reader);
+    let env = Environment::new(
+        initial_input.player_size,
+        initial_input.deck_size,
+        initial_input.hand_size,
+        initial_input.max_turn,
+        initial_input.is_deplicated_pick_enabled,
+    );
+    let player_id = initial_input.player_id;
+    let deck = agent
+        .choose_deck(&env, &initial_input.cards, &initial_input.field, player_id)
+        .expect("プロセス内のbotは正しく行動する");
+    writeln!(writer, "{}", format_card_ids(&deck))?;
+    writer.flush()?;
+
+    let hands = read_hands(reader);
+    let mulligan = agent
+        .mulligan(&hands)
+        .expect("プロセス内のbotは正しく行動する");
+    writeln!(writer, "{}", mulligan)?;
+    writer.flush()?;
+
+    let mut played = vec![];
+    loop {
+        let turn_input = read_turn_input(reader, initial_input.field_size_y);
+        // 山札は選んだデッキから手札と出したカードを除いたもの
+        let mut rest = deck.clone();
+        let played_cards = played.iter().map(|action: &Action| action.get_card_id());
+        for card_id in turn_input.hands.iter().copied().chain(played_cards) {
+            if let Some(index) = rest.iter().position(|&c| c == card_id) {
+                rest.swap_remove(index);
+            }
+        }
+        rest.sort();
+        // 全員同じ枚数を引くので、手札と山札の枚数は自分と同じ
+        let mut played_by_all = vec![vec![]; env.player_size];
+        played_by_all[player_id] = played.clone();
+        let observation = Observation {
+            player_id,
+            turn: turn_input.turn,
+            field: turn_input.field,
+            special_points: turn_input.special_points,
+            hand_sizes: vec![turn_input.hands.len(); env.player_size],
+            deck_sizes: vec![rest.len(); env.player_size],
+            hands: turn_input.hands,
+            deck: rest,
+            played: played_by_all,
+        };
+        let action = agent
+            .act(&observation, &turn_input.valid_actions)
+            .expect("プロセス内のbotは正しく行動する");
+        writeln!(writer, "{}", action)?;
+        writer.flush()?;
+        played.push(action);
+
+        if turn_input.turn == initial_input.max_turn {
+            return Ok(());
+        }
+    }
+}
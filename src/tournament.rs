@@ -0,0 +1,460 @@
+//! 複数のbotの総当たり戦・ガントレット戦。対局は`Match::run`で並列に進め、結果を対戦表とレーティングにまとめる。
+use crate::{
+    agent::Agent,
+    check_field,
+    match_runner::{FailurePolicy, Match},
+    record::GameRecord,
+    seed::derive_seed,
+    Card, Environment, Field, GameError,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// 対戦の組み合わせ方
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub enum Format {
+    /// すべての組み合わせで対戦する
+    #[default]
+    RoundRobin,
+    /// 最初のbotが残りのbotそれぞれと対戦する
+    Gauntlet,
+}
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Format::RoundRobin => write!(f, "round_robin"),
+            Format::Gauntlet => write!(f, "gauntlet"),
+        }
+    }
+}
+impl FromStr for Format {
+    type Err = GameError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round_robin" => Ok(Format::RoundRobin),
+            "gauntlet" => Ok(Format::Gauntlet),
+            _ => Err(GameError::MalformedText(s.to_string())),
+        }
+    }
+}
+
+/// 予定された1局。`seats[player_id]`がそのプレイヤーとして座るbotの番号。
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ScheduledGame {
+    pub index: usize,
+    pub seats: [usize; 2],
+    pub seed: u64,
+}
+
+/// 組み合わせごとに`games_per_pairing`局を予定する。先手後手の有利不利が偏らないよう1局ごとに席を入れ替える。
+pub fn schedule(
+    n_bots: usize,
+    format: Format,
+    games_per_pairing: usize,
+    seed: u64,
+) -> Vec<ScheduledGame> {
+    let pairings = match format {
+        Format::RoundRobin => (0..n_bots)
+            .flat_map(|i| (i + 1..n_bots).map(move |j| (i, j)))
+            .collect::<Vec<_>>(),
+        Format::Gauntlet => (1..n_bots).map(|j| (0, j)).collect(),
+    };
+    let mut games = vec![];
+    for (i, j) in pairings {
+        for k in 0..games_per_pairing {
+            let index = games.len();
+            games.push(ScheduledGame {
+                index,
+                seats: if k % 2 == 0 { [i, j] } else { [j, i] },
+                seed: derive_seed(seed, index as u64),
+            });
+        }
+    }
+    games
+}
+
+/// `n_threads`個のスレッドで対局を進め、予定と同じ順に記録を返す。
+/// botは対局ごとにそのスレッドの中で`new_agents`で作るので、外部プロセスのbotもそのまま使える。
+/// botが応答しなくなったときの扱いは`policy`に従う。対局できない設定やbotを作れなかったときは、
+/// 残りの対局を始めずに予定の順で最初のエラーを返す。
+pub fn run_games(
+    env: &Environment,
+    field: &Field,
+    catalog: &[Card],
+    games: &[ScheduledGame],
+    n_threads: usize,
+    policy: FailurePolicy,
+    new_agents: impl Fn(&ScheduledGame) -> Result<Vec<Box<dyn Agent>>, GameError> + Sync,
+) -> Result<Vec<GameRecord>, GameError> {
+    // botを起動する前にフィールドを確かめる
+    check_field(env, field)?;
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let records = Mutex::new(vec![None; games.len()]);
+    std::thread::scope(|scope| {
+        for _ in 0..n_threads.max(1) {
+            scope.spawn(|| loop {
+                if failed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(game) = games.get(index) else {
+                    break;
+                };
+                let record = new_agents(game).and_then(|mut agents| {
+                    Match::run_with_policy(env, field, catalog, &mut agents, game.seed, policy)
+                });
+                if record.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                records.lock().expect("他のスレッドはpanicしていない")[index] = Some(record);
+            });
+        }
+    });
+    let records = records.into_inner().expect("他のスレッドはpanicしていない");
+    // エラーで打ち切ったときは始めなかった対局があるので、先にエラーを探す
+    if let Some(why) = records
+        .iter()
+        .flatten()
+        .find_map(|record| record.as_ref().err())
+    {
+        return Err(why.clone());
+    }
+    records
+        .into_iter()
+        .map(|record| record.expect("すべての対局を終えた"))
+        .collect()
+}
+
+/// 2つのbotの対戦成績（勝ち・引き分け・負けとマス数の差）。`Crosstable`の行のbotから見た値。
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct PairStats {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    /// 自分のマス数から相手のマス数を引いた値の合計
+    pub margin: i64,
+}
+impl PairStats {
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+    /// 引き分けを0.5勝とした得点
+    pub fn score(&self) -> f64 {
+        self.wins as f64 + 0.5 * self.draws as f64
+    }
+    pub fn average_margin(&self) -> f64 {
+        self.margin as f64 / self.games().max(1) as f64
+    }
+    fn add(&mut self, other: &PairStats) {
+        self.wins += other.wins;
+        self.draws += other.draws;
+        self.losses += other.losses;
+        self.margin += other.margin;
+    }
+}
+
+/// Eloの尺度に直したBradley–Terryモデルの強さ。平均が0になるようにしてある。
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Rating {
+    pub elo: f64,
+    /// 95%信頼区間の半分の幅
+    pub error: f64,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Crosstable {
+    pub names: Vec<String>,
+    /// `(i, j)`はbot iから見たbot jとの成績。対戦した組み合わせだけが入る。
+    pub pairs: BTreeMap<(usize, usize), PairStats>,
+    pub ratings: Vec<Rating>,
+}
+impl Crosstable {
+    pub fn new(names: &[String], games: &[ScheduledGame], records: &[GameRecord]) -> Self {
+        let mut pairs = BTreeMap::<(usize, usize), PairStats>::new();
+        for (game, record) in games.iter().zip(records.iter()) {
+            for (player_id, &bot) in game.seats.iter().enumerate() {
+                let opponent_id = 1 - player_id;
+                let stats = pairs.entry((bot, game.seats[opponent_id])).or_default();
+                match record.ranking[player_id].cmp(&record.ranking[opponent_id]) {
+                    std::cmp::Ordering::Less => stats.wins += 1,
+                    std::cmp::Ordering::Equal => stats.draws += 1,
+                    std::cmp::Ordering::Greater => stats.losses += 1,
+                }
+                stats.margin += record.scores[player_id] as i64 - record.scores[opponent_id] as i64;
+            }
+        }
+        let ratings = bradley_terry(names.len(), &pairs);
+        Self {
+            names: names.to_vec(),
+            pairs,
+            ratings,
+        }
+    }
+    /// bot iの全対戦の合計
+    pub fn total(&self, bot: usize) -> PairStats {
+        let mut total = PairStats::default();
+        for (_, stats) in self.pairs.range((bot, 0)..(bot + 1, 0)) {
+            total.add(stats);
+        }
+        total
+    }
+}
+impl Display for Crosstable {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let n_bots = self.names.len();
+        write!(f, "{:<3} {:<24}", "#", "bot")?;
+        for j in 0..n_bots {
+            write!(f, " {:>16}", j)?;
+        }
+        writeln!(f, " {:>16} {:>16}", "total", "Elo")?;
+        // 強い順に並べる
+        let mut order = (0..n_bots).collect::<Vec<usize>>();
+        order.sort_by(|&a, &b| self.ratings[b].elo.total_cmp(&self.ratings[a].elo));
+        for &i in order.iter() {
+            write!(f, "{:<3} {:<24}", i, self.names[i])?;
+            for j in 0..n_bots {
+                match self.pairs.get(&(i, j)) {
+                    Some(stats) => write!(
+                        f,
+                        " {:>16}",
+                        format!(
+                            "{}-{}-{} {:+.1}",
+                            stats.wins,
+                            stats.draws,
+                            stats.losses,
+                            stats.average_margin()
+                        )
+                    )?,
+                    None => write!(f, " {:>16}", "-")?,
+                }
+            }
+            let total = self.total(i);
+            writeln!(
+                f,
+                " {:>16} {:>16}",
+                format!(
+                    "{}-{}-{} {:+.1}",
+                    total.wins,
+                    total.draws,
+                    total.losses,
+                    total.average_margin()
+                ),
+                format!("{:+.0} ± {:.0}", self.ratings[i].elo, self.ratings[i].error)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// 自然対数の強さ1あたりのElo
+const ELO_PER_NATURAL: f64 = 400.0 / std::f64::consts::LN_10;
+// 95%信頼区間の標準正規分布の分位点
+const Z_95: f64 = 1.96;
+
+// 全勝・全敗でも発散しないよう、対戦した組み合わせごとに1局の引き分けを加えてからMMアルゴリズムで最尤推定する。
+// 信頼区間はFisher情報行列（重み付きラプラシアン）の擬似逆行列から求める。
+fn bradley_terry(n_bots: usize, pairs: &BTreeMap<(usize, usize), PairStats>) -> Vec<Rating> {
+    let mut games = vec![vec![0.0; n_bots]; n_bots];
+    let mut scores = vec![0.0; n_bots];
+    for (&(i, j), stats) in pairs.iter() {
+        games[i][j] = stats.games() as f64 + 1.0;
+        scores[i] += stats.score() + 0.5;
+    }
+    let mut strengths = vec![1.0; n_bots];
+    for _ in 0..10000 {
+        let mut next = (0..n_bots)
+            .map(|i| {
+                let denominator = (0..n_bots)
+                    .filter(|&j| games[i][j] > 0.0)
+                    .map(|j| games[i][j] / (strengths[i] + strengths[j]))
+                    .sum::<f64>();
+                if denominator > 0.0 {
+                    scores[i] / denominator
+                } else {
+                    strengths[i]
+                }
+            })
+            .collect::<Vec<f64>>();
+        // 幾何平均を1にそろえる
+        let mean = next.iter().map(|s| s.ln()).sum::<f64>() / n_bots as f64;
+        next.iter_mut().for_each(|s| *s /= mean.exp());
+        let change = next
+            .iter()
+            .zip(strengths.iter())
+            .map(|(a, b)| (a.ln() - b.ln()).abs())
+            .fold(0.0, f64::max);
+        strengths = next;
+        if change < 1e-10 {
+            break;
+        }
+    }
+
+    let mut laplacian = vec![vec![0.0; n_bots]; n_bots];
+    for i in 0..n_bots {
+        for j in 0..n_bots {
+            if i != j && games[i][j] > 0.0 {
+                let p = strengths[i] / (strengths[i] + strengths[j]);
+                let weight = games[i][j] * p * (1.0 - p);
+                laplacian[i][j] -= weight;
+                laplacian[i][i] += weight;
+            }
+        }
+    }
+    // 連結なグラフのラプラシアンLについて L⁺ = (L + J/n)⁻¹ - J/n
+    let uniform = 1.0 / n_bots as f64;
+    let shifted = laplacian
+        .iter()
+        .map(|row| row.iter().map(|v| v + uniform).collect())
+        .collect::<Vec<Vec<f64>>>();
+    let covariance = invert(shifted);
+    (0..n_bots)
+        .map(|i| Rating {
+            elo: ELO_PER_NATURAL * strengths[i].ln(),
+            error: covariance.as_ref().map_or(f64::INFINITY, |covariance| {
+                Z_95 * ELO_PER_NATURAL * (covariance[i][i] - uniform).max(0.0).sqrt()
+            }),
+        })
+        .collect()
+}
+
+// Gauss-Jordan法の逆行列。正則でなければNone。
+fn invert(mut matrix: Vec<Vec<f64>>) -> Option<Vec<Vec<f64>>> {
+    let n = matrix.len();
+    let mut inverse = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect::<Vec<Vec<f64>>>();
+    for column in 0..n {
+        let pivot = (column..n)
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))?;
+        if matrix[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        inverse.swap(column, pivot);
+        let scale = matrix[column][column];
+        for j in 0..n {
+            matrix[column][j] /= scale;
+            inverse[column][j] /= scale;
+        }
+        for row in 0..n {
+            if row != column {
+                let factor = matrix[row][column];
+                for j in 0..n {
+                    matrix[row][j] -= factor * matrix[column][j];
+                    inverse[row][j] -= factor * inverse[column][j];
+                }
+            }
+        }
+    }
+    Some(inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::RandomAgent, catalog::parse_card_catalog, seed::game_rng};
+    #[test]
+    fn test_schedule() {
+        let games = schedule(3, Format::RoundRobin, 4, 0);
+        assert_eq!(games.len(), 3 * 4);
+        assert_eq!(games[0].seats, [0, 1]);
+        assert_eq!(games[1].seats, [1, 0]);
+        assert_eq!(games[11].seats, [2, 1]);
+        assert_ne!(games[0].seed, games[1].seed);
+        let games = schedule(4, Format::Gauntlet, 2, 0);
+        assert_eq!(games.len(), 3 * 2);
+        assert!(games.iter().all(|game| game.seats.contains(&0)));
+        assert_eq!("gauntlet".parse::<Format>(), Ok(Format::Gauntlet));
+    }
+    #[test]
+    fn test_bradley_terry() {
+        let stats = |wins, draws, losses| PairStats {
+            wins,
+            draws,
+            losses,
+            margin: 0,
+        };
+        let mut pairs = BTreeMap::new();
+        pairs.insert((0, 1), stats(8, 0, 2));
+        pairs.insert((1, 0), stats(2, 0, 8));
+        pairs.insert((1, 2), stats(5, 0, 5));
+        pairs.insert((2, 1), stats(5, 0, 5));
+        let ratings = bradley_terry(3, &pairs);
+        assert!(ratings[0].elo > ratings[1].elo);
+        assert!((ratings[1].elo - ratings[2].elo).abs() < 1e-6);
+        assert!(ratings.iter().map(|r| r.elo).sum::<f64>().abs() < 1e-6);
+        // 直接対戦していないbot 2の方が不確か
+        assert!(ratings[2].error > ratings[1].error);
+        assert!(ratings.iter().all(|r| r.error.is_finite() && r.error > 0.0));
+    }
+    #[test]
+    fn test_run_games() {
+        let env = Environment::new(2, 6, 4, 3, false);
+        let catalog =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        let names = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let games = schedule(names.len(), Format::RoundRobin, 2, 0);
+        let run = |n_threads| {
+            run_games(
+                &env,
+                &Field::default(),
+                &catalog,
+                &games,
+                n_threads,
+                FailurePolicy::Forfeit,
+                |game| {
+                    Ok(game
+                        .seats
+                        .iter()
+                        .enumerate()
+                        .map(|(player_id, &bot)| -> Box<dyn Agent> {
+                            Box::new(RandomAgent::new(
+                                &names[bot],
+                                game_rng(derive_seed(game.seed, player_id as u64)),
+                            ))
+                        })
+                        .collect())
+                },
+            )
+            .unwrap()
+        };
+        let records = run(3);
+        // スレッド数によらず同じ結果になる
+        assert_eq!(records, run(1));
+        for (game, record) in games.iter().zip(records.iter()) {
+            assert_eq!(record.names[0], names[game.seats[0]]);
+        }
+        let crosstable = Crosstable::new(&names, &games, &records);
+        for i in 0..names.len() {
+            assert_eq!(crosstable.total(i).games(), 4);
+        }
+        let stats = crosstable.pairs[&(0, 1)];
+        let reverse = crosstable.pairs[&(1, 0)];
+        assert_eq!((stats.wins, stats.losses), (reverse.losses, reverse.wins));
+        assert_eq!(stats.margin, -reverse.margin);
+        assert!(crosstable.to_string().lines().count() == names.len() + 1);
+
+        // botを作れなければ対局を打ち切ってエラーを返す
+        let failed = run_games(
+            &env,
+            &Field::default(),
+            &catalog,
+            &games,
+            2,
+            FailurePolicy::Forfeit,
+            |_| Err(GameError::SpawnFailed("no such bot".to_string())),
+        );
+        assert_eq!(
+            failed,
+            Err(GameError::SpawnFailed("no such bot".to_string()))
+        );
+    }
+}
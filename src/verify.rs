@@ -0,0 +1,357 @@
+//! 棋譜を記録された山札から`State::apply`で再生し、記録と食い違うところを探す。
+//! エンジンの変更で過去の対局の結果が変わっていないかや、棋譜が壊れていないかの確認に使う。
+use crate::{
+    kifu::{Kifu, KifuError},
+    seed::game_rng,
+    setup::{GameSetup, SetupPhase},
+    Action, Card, CardId, Environment, PlayerId, State,
+};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+};
+
+/// 棋譜と再生した結果が最初に食い違ったところ
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Divergence {
+    /// 対局の設定や開始時の山札が対局として成り立たない
+    Setup(KifuError),
+    /// 記録された種でシャッフルしても記録された山札の順番にならない
+    Shuffle { player_id: PlayerId },
+    /// 合法手ではないアクション
+    IllegalAction {
+        turn: usize,
+        player_id: PlayerId,
+        action: Action,
+    },
+    /// 記録されたターンの結果と再生した結果が違う
+    Outcome { turn: usize },
+    /// 反則の記録が無いのに途中で終わっている
+    Incomplete { turn: usize },
+    /// 最終ターンの後にもアクションが記録されている
+    ExtraTurn { turn: usize },
+    /// 反則の記録と再生した結果が合わない
+    Forfeit {
+        recorded: Option<PlayerId>,
+        replayed: Option<PlayerId>,
+    },
+    /// 記録されたマス数と再生したマス数が違う
+    Scores {
+        recorded: Vec<usize>,
+        replayed: Vec<usize>,
+    },
+    /// 記録された順位と再生した順位が違う
+    Ranking {
+        recorded: Vec<usize>,
+        replayed: Vec<usize>,
+    },
+}
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Divergence::Setup(why) => write!(f, "setup: {}", why),
+            Divergence::Shuffle { player_id } => write!(
+                f,
+                "player {}: shuffled deck doesn't match the seed",
+                player_id
+            ),
+            Divergence::IllegalAction {
+                turn,
+                player_id,
+                action,
+            } => write!(
+                f,
+                "turn {}: player {} played illegal action {}",
+                turn, player_id, action
+            ),
+            Divergence::Outcome { turn } => {
+                write!(f, "turn {}: outcome differs from the replay", turn)
+            }
+            Divergence::Incomplete { turn } => {
+                write!(f, "turn {}: record ends without a forfeit", turn)
+            }
+            Divergence::ExtraTurn { turn } => write!(f, "turn {}: game is already over", turn),
+            Divergence::Forfeit { recorded, replayed } => write!(
+                f,
+                "forfeit: recorded {:?} but replayed {:?}",
+                recorded, replayed
+            ),
+            Divergence::Scores { recorded, replayed } => write!(
+                f,
+                "scores: recorded {:?} but replayed {:?}",
+                recorded, replayed
+            ),
+            Divergence::Ranking { recorded, replayed } => write!(
+                f,
+                "ranking: recorded {:?} but replayed {:?}",
+                recorded, replayed
+            ),
+        }
+    }
+}
+impl std::error::Error for Divergence {}
+
+/// 再生し終えた対局
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Replay {
+    /// 最後まで再生したターンの数
+    pub turns: usize,
+    pub scores: Vec<usize>,
+    /// 反則で終わった場合の反則したプレイヤー
+    pub forfeit: Option<PlayerId>,
+}
+
+/// 棋譜を最初から再生し、すべてのアクションが合法で、記録された結果と一致することを確かめる。
+/// 種が記録されていれば、山札の順番がその種から再現できることも確かめる。
+pub fn verify(kifu: &Kifu) -> Result<Replay, Divergence> {
+    let env = kifu.environment().map_err(Divergence::Setup)?;
+    let field = kifu.field().map_err(Divergence::Setup)?;
+    let catalog = kifu.cards().map_err(Divergence::Setup)?;
+    let cards = catalog
+        .iter()
+        .map(|card| (card.id, card))
+        .collect::<HashMap<CardId, &Card>>();
+    let recorded_forfeit = kifu
+        .result
+        .as_ref()
+        .and_then(|result| result.forfeit.as_ref())
+        .map(|forfeit| forfeit.player_id);
+
+    // 人数より多い記録は反則では説明できない
+    kifu.check_player_size(&env).map_err(Divergence::Setup)?;
+    let mut state = match kifu.start(&env, &cards, &field) {
+        Ok(state) => state,
+        // 対局を始める前の反則では山札がそろわない
+        Err(_) if recorded_forfeit.is_some() && kifu.turns.is_empty() => {
+            return Ok(Replay {
+                turns: 0,
+                scores: vec![0; env.player_size],
+                forfeit: recorded_forfeit,
+            })
+        }
+        Err(why) => return Err(Divergence::Setup(why)),
+    };
+    if let Some(seed) = kifu.metadata.seed {
+        check_shuffles(&env, &cards, kifu, seed)?;
+    }
+
+    let mut forfeit = None;
+    for (index, turn) in kifu.turns.iter().enumerate() {
+        if state.is_done(&env) {
+            return Err(Divergence::ExtraTurn { turn: index + 1 });
+        }
+        let is_last = index + 1 == kifu.turns.len();
+        forfeit = replay_turn(&env, &cards, &mut state, &turn.actions, is_last)?;
+        if forfeit.is_some() {
+            break;
+        }
+        let outcome = state
+            .apply(&env, &cards, &turn.actions)
+            .expect("合法手だけなので適用できる");
+        if turn
+            .outcome
+            .as_ref()
+            .is_some_and(|recorded| *recorded != outcome)
+        {
+            return Err(Divergence::Outcome { turn: outcome.turn });
+        }
+    }
+    if forfeit.is_none() && !state.is_done(&env) {
+        return Err(Divergence::Incomplete { turn: state.turn });
+    }
+
+    let replay = Replay {
+        turns: kifu.turns.len() - usize::from(forfeit.is_some()),
+        scores: state.scores(),
+        forfeit,
+    };
+    let Some(result) = &kifu.result else {
+        return Ok(replay);
+    };
+    if recorded_forfeit != forfeit {
+        return Err(Divergence::Forfeit {
+            recorded: recorded_forfeit,
+            replayed: forfeit,
+        });
+    }
+    if result.scores != replay.scores {
+        return Err(Divergence::Scores {
+            recorded: result.scores.clone(),
+            replayed: replay.scores,
+        });
+    }
+    // 反則で終わった対局の順位は反則したプレイヤーを最下位にしたものなので、再生した盤面からは決まらない
+    if forfeit.is_none() && result.ranking != state.ranking(&env) {
+        return Err(Divergence::Ranking {
+            recorded: result.ranking.clone(),
+            replayed: state.ranking(&env),
+        });
+    }
+    Ok(replay)
+}
+
+// 1ターン分のアクションが合法か確かめる。
+// 反則で終わったのは最後のターンだけなので、そのときは反則したプレイヤーを返す。
+fn replay_turn(
+    env: &Environment,
+    cards: &HashMap<CardId, &Card>,
+    state: &mut State,
+    actions: &[Action],
+    is_last: bool,
+) -> Result<Option<PlayerId>, Divergence> {
+    for (player_id, action) in actions.iter().enumerate() {
+        if !state
+            .generate_valid_actions(cards, player_id)
+            .contains(action)
+        {
+            // 不正なアクションは全員のアクションをそろえてから反則になる
+            if is_last {
+                return Ok(Some(player_id));
+            }
+            return Err(Divergence::IllegalAction {
+                turn: state.turn,
+                player_id,
+                action: *action,
+            });
+        }
+    }
+    if actions.len() < env.player_size {
+        if !is_last {
+            return Err(Divergence::Incomplete { turn: state.turn });
+        }
+        // アクションを返さなかったプレイヤーの反則
+        return Ok(Some(actions.len()));
+    }
+    Ok(None)
+}
+
+// `Match::run`と同じ順番で、記録された種から山札をシャッフルし直す
+fn check_shuffles(
+    env: &Environment,
+    cards: &HashMap<CardId, &Card>,
+    kifu: &Kifu,
+    seed: u64,
+) -> Result<(), Divergence> {
+    let mut rng = game_rng(seed);
+    let mut setup = GameSetup::new(env);
+    // `Kifu::start`で全員分そろっていることは確かめてある
+    for player_id in 0..env.player_size {
+        setup
+            .pick_deck(env, cards, player_id, &kifu.picked_decks[player_id])
+            .map_err(|why| Divergence::Setup(KifuError::Invalid(why.to_string())))?;
+    }
+    for player_id in 0..env.player_size {
+        setup.shuffle(player_id, &mut rng);
+        if setup.deck(player_id) != kifu.shuffled_decks[player_id] {
+            return Err(Divergence::Shuffle { player_id });
+        }
+        setup.mulligan(player_id, kifu.mulligans[player_id]);
+        if setup.phase(player_id) == SetupPhase::Redeal {
+            setup.shuffle(player_id, &mut rng);
+        }
+        if setup.deck(player_id) != kifu.decks[player_id] {
+            return Err(Divergence::Shuffle { player_id });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::{Agent, RandomAgent},
+        catalog::parse_card_catalog,
+        match_runner::Match,
+        text_protocol::format_record,
+        Field, MulliganAction,
+    };
+
+    #[test]
+    fn test_verify() {
+        let env = Environment::new(2, 6, 4, 3, false);
+        let catalog =
+            parse_card_catalog(include_str!("../resources/card_catalog_sample.json")).unwrap();
+        let field = Field::default();
+        let mut agents: Vec<Box<dyn Agent>> = vec![
+            Box::new(RandomAgent::new("a", game_rng(0))),
+            Box::new(RandomAgent::new("b", game_rng(1))),
+        ];
+        let record = Match::run(&env, &field, &catalog, &mut agents, 3).unwrap();
+        let kifu = Kifu::new(&env, &field, &catalog, &record);
+        assert_eq!(
+            verify(&kifu),
+            Ok(Replay {
+                turns: env.max_turn,
+                scores: record.scores.clone(),
+                forfeit: None,
+            })
+        );
+        // テキストの棋譜には結果が無いが、再生はできる
+        let legacy =
+            crate::kifu::parse_kifu(&format_record(&env, &catalog, &field.shape, &record)).unwrap();
+        assert_eq!(
+            verify(&legacy).map(|replay| replay.scores),
+            Ok(record.scores)
+        );
+
+        let mut tampered = kifu.clone();
+        tampered.turns[0].actions[0] = Action::Pass { card_id: 0 };
+        assert_eq!(
+            verify(&tampered),
+            Err(Divergence::IllegalAction {
+                turn: 1,
+                player_id: 0,
+                action: Action::Pass { card_id: 0 },
+            })
+        );
+        let mut tampered = kifu.clone();
+        tampered.turns[1]
+            .outcome
+            .as_mut()
+            .unwrap()
+            .special_points_from_pass[0] += 1;
+        assert_eq!(verify(&tampered), Err(Divergence::Outcome { turn: 2 }));
+        let mut tampered = kifu.clone();
+        tampered.result.as_mut().unwrap().scores[1] += 1;
+        assert!(matches!(verify(&tampered), Err(Divergence::Scores { .. })));
+        let mut tampered = kifu.clone();
+        tampered.metadata.seed = Some(4);
+        assert_eq!(verify(&tampered), Err(Divergence::Shuffle { player_id: 0 }));
+        // 人数より多い記録は再生せずに壊れた棋譜として扱う
+        let mut tampered = kifu.clone();
+        tampered.mulligans.push(MulliganAction::Pass);
+        assert!(matches!(
+            verify(&tampered),
+            Err(Divergence::Setup(KifuError::Invalid(_)))
+        ));
+        let mut tampered = kifu.clone();
+        tampered.picked_decks.push(kifu.picked_decks[0].clone());
+        tampered.result = None;
+        assert!(matches!(
+            verify(&tampered),
+            Err(Divergence::Setup(KifuError::Invalid(_)))
+        ));
+        let mut tampered = kifu.clone();
+        tampered.turns[0].actions.push(kifu.turns[0].actions[0]);
+        assert!(matches!(
+            verify(&tampered),
+            Err(Divergence::Setup(KifuError::Invalid(_)))
+        ));
+        let mut tampered = kifu.clone();
+        tampered.turns.pop();
+        assert_eq!(verify(&tampered), Err(Divergence::Incomplete { turn: 3 }));
+
+        // 途中でアクションを返さなかった反則
+        let mut forfeited = kifu.clone();
+        forfeited.turns.truncate(2);
+        forfeited.turns[1].actions.pop();
+        assert_eq!(
+            verify(&forfeited),
+            Err(Divergence::Forfeit {
+                recorded: None,
+                replayed: Some(1),
+            })
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! 局面のZobristハッシュに使う乱数。
+//! 盤面のサイズやカードの種類に依存しないよう、表を持たずに要素ごとの値から乱数を作る。
+use crate::{CardId, FieldSquareType, PlayerId};
+
+const SQUARE: u64 = 1;
+const SPECIAL_POINT: u64 = 2;
+const TURN: u64 = 3;
+const HAND: u64 = 4;
+
+// splitmix64
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+pub(crate) fn key(values: &[u64]) -> u64 {
+    values.iter().fold(0, |acc, &v| mix(acc ^ v))
+}
+
+/// 空白マスは0にしておき、空白の多い盤面での計算を省く
+pub fn square_key(y: usize, x: usize, square: FieldSquareType) -> u64 {
+    let (kind, player_id) = match square {
+        FieldSquareType::Empty => return 0,
+        FieldSquareType::Colored { player_id } => (1, player_id),
+        FieldSquareType::Special {
+            player_id,
+            activeted: false,
+        } => (2, player_id),
+        FieldSquareType::Special {
+            player_id,
+            activeted: true,
+        } => (3, player_id),
+        FieldSquareType::Block => (4, 0),
+    };
+    key(&[SQUARE, y as u64, x as u64, kind, player_id as u64])
+}
+pub fn special_point_key(player_id: PlayerId, special_point: usize) -> u64 {
+    key(&[SPECIAL_POINT, player_id as u64, special_point as u64])
+}
+pub fn turn_key(turn: usize) -> u64 {
+    key(&[TURN, turn as u64])
+}
+/// 手札は順番を区別しない。同じカードが複数ある場合に打ち消し合わないよう、何枚目かも含める。
+pub fn hand_key(player_id: PlayerId, hands: &[CardId]) -> u64 {
+    let mut hash = 0;
+    for (i, card_id) in hands.iter().enumerate() {
+        let copy_index = hands[..i].iter().filter(|&c| c == card_id).count();
+        hash ^= key(&[HAND, player_id as u64, *card_id as u64, copy_index as u64]);
+    }
+    hash
+}
@@ -0,0 +1,60 @@
+use std::io::{self, Read};
+use tableturfbattle::{
+    kifu::{load_kifu, parse_kifu, Kifu, KifuError},
+    verify::verify,
+};
+
+// 棋譜を読み込み、再生して記録と一致するか確かめる。一致しなければfalseを返す
+fn check(name: &str, kifu: Result<Kifu, KifuError>) -> bool {
+    let kifu = match kifu {
+        Ok(kifu) => kifu,
+        Err(why) => {
+            println!("{}: {}", name, why);
+            return false;
+        }
+    };
+    match verify(&kifu) {
+        Ok(replay) => {
+            let scores = replay
+                .scores
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<String>>()
+                .join(" : ");
+            match replay.forfeit {
+                Some(player_id) => println!(
+                    "{}: ok ({} turns, player{} forfeited, {})",
+                    name, replay.turns, player_id, scores
+                ),
+                None => println!("{}: ok ({} turns, {})", name, replay.turns, scores),
+            }
+            true
+        }
+        Err(why) => {
+            println!("{}: {}", name, why);
+            false
+        }
+    }
+}
+fn main() {
+    // `verify kifu1.json kifu2.txt ...`で棋譜を検証する。引数が無ければ標準入力から1つ読む。
+    // 1つでも記録と食い違えば終了コード1で終わる
+    let paths = std::env::args().skip(1).collect::<Vec<String>>();
+    let ok = if paths.is_empty() {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .expect("標準入力から読める");
+        check("-", parse_kifu(&text))
+    } else {
+        // 食い違いが見つかっても残りの棋譜も検証する
+        let failed = paths
+            .iter()
+            .filter(|path| !check(path, load_kifu(path)))
+            .count();
+        failed == 0
+    };
+    if !ok {
+        std::process::exit(1);
+    }
+}